@@ -0,0 +1,180 @@
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+
+pub type CommodityFormats = HashMap<String, CommodityFormat>;
+
+/// The way amounts of a commodity are written, like `$1,000.00` or
+/// `1.000,00 €`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommodityFormat {
+    pub suffix: bool,
+    pub separated: bool,
+    pub decimal_mark: char,
+    pub thousands_separator: Option<char>,
+    pub precision: usize,
+}
+
+impl Default for CommodityFormat {
+    fn default() -> CommodityFormat {
+        CommodityFormat {
+            suffix: false,
+            separated: true,
+            decimal_mark: '.',
+            thousands_separator: None,
+            precision: 0,
+        }
+    }
+}
+
+impl CommodityFormat {
+    /// Parses a sample amount, in the same way as ledger's `commodity`
+    /// directive does. Returns the commodity together with its format.
+    pub fn parse(sample: &str) -> Option<(String, CommodityFormat)> {
+        let sample = sample.trim();
+        let first_digit = sample.find(|c: char| c.is_ascii_digit())?;
+        let last_digit = sample.rfind(|c: char| c.is_ascii_digit())?;
+        let prefix = sample[..first_digit].trim_end_matches('-');
+        let number = &sample[first_digit..=last_digit];
+        let suffix = &sample[last_digit + 1..];
+        let (commodity, suffix, separated) = match (prefix.trim(), suffix.trim()) {
+            ("", "") => return None,
+            (commodity, "") => (commodity, false, prefix != prefix.trim_end()),
+            ("", commodity) => (commodity, true, suffix != suffix.trim_start()),
+            _ => return None,
+        };
+        let is_number_char = |c: char| c.is_ascii_digit() || ".,' ".contains(c);
+        if !number.chars().all(is_number_char)
+            || commodity.contains(|c: char| "-{}()[]@\"".contains(c))
+        {
+            return None;
+        }
+
+        let marks: Vec<(usize, char)> = number
+            .char_indices()
+            .filter(|(_, c)| !c.is_ascii_digit())
+            .collect();
+        let mut format = CommodityFormat {
+            suffix,
+            separated,
+            ..CommodityFormat::default()
+        };
+        if let Some((last_pos, last_mark)) = marks.last() {
+            let digits_after = number.len() - last_pos - last_mark.len_utf8();
+            let single_kind = marks.iter().all(|(_, c)| c == last_mark);
+            if single_kind && (marks.len() > 1 || digits_after == 3) {
+                format.thousands_separator = Some(*last_mark);
+            } else {
+                format.decimal_mark = *last_mark;
+                format.precision = digits_after;
+                format.thousands_separator = marks.iter().map(|(_, c)| *c).find(|c| c != last_mark);
+            }
+        }
+        Some((commodity.to_owned(), format))
+    }
+
+    pub fn format(&self, commodity: &str, amount: Decimal) -> String {
+        self.place_commodity(commodity, &self.format_number(amount))
+    }
+
+    /// Writes the commodity next to an already formatted number.
+    pub fn place_commodity(&self, commodity: &str, number: &str) -> String {
+        let space = if self.separated { " " } else { "" };
+        if self.suffix {
+            format!("{}{}{}", number, space, commodity)
+        } else {
+            format!("{}{}{}", commodity, space, number)
+        }
+    }
+
+    /// Formats just the number of an amount, without its commodity.
+    pub fn format_number(&self, amount: Decimal) -> String {
+        let unsigned = amount.abs().to_string();
+        let mut parts = unsigned.splitn(2, '.');
+        let integer = parts.next().unwrap_or("0");
+        let mut fraction = parts.next().unwrap_or("").to_owned();
+        while fraction.len() < self.precision {
+            fraction.push('0');
+        }
+
+        let mut number = String::new();
+        if amount.is_sign_negative() && amount != Decimal::new(0, 0) {
+            number.push('-');
+        }
+        for (i, digit) in integer.chars().enumerate() {
+            if i > 0 && (integer.len() - i).is_multiple_of(3) {
+                if let Some(separator) = self.thousands_separator {
+                    number.push(separator);
+                }
+            }
+            number.push(digit);
+        }
+        if !fraction.is_empty() {
+            number.push(self.decimal_mark);
+            number.push_str(&fraction);
+        }
+        number
+    }
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use rust_decimal_macros::*;
+
+    #[test]
+    fn prefix_format() {
+        let (commodity, format) = CommodityFormat::parse("$1,000.00").unwrap();
+        assert_eq!(commodity, "$");
+        assert_eq!(
+            format,
+            CommodityFormat {
+                suffix: false,
+                separated: false,
+                decimal_mark: '.',
+                thousands_separator: Some(','),
+                precision: 2,
+            }
+        );
+        assert_eq!(format.format("$", dec!(1234567.5)), "$1,234,567.50");
+        assert_eq!(format.format("$", dec!(-12)), "$-12.00");
+        assert_eq!(format.format("$", dec!(0.125)), "$0.125");
+    }
+
+    #[test]
+    fn suffix_format() {
+        let (commodity, format) = CommodityFormat::parse("1.000,00 €").unwrap();
+        assert_eq!(commodity, "€");
+        assert_eq!(
+            format,
+            CommodityFormat {
+                suffix: true,
+                separated: true,
+                decimal_mark: ',',
+                thousands_separator: Some('.'),
+                precision: 2,
+            }
+        );
+        assert_eq!(format.format("€", dec!(1234.5)), "1.234,50 €");
+        assert_eq!(format.format("€", dec!(-5)), "-5,00 €");
+    }
+
+    #[test]
+    fn other_formats() {
+        let (commodity, format) = CommodityFormat::parse("CZK 1000").unwrap();
+        assert_eq!(commodity, "CZK");
+        assert_eq!(format, CommodityFormat::default());
+        assert_eq!(format.format("CZK", dec!(120.5)), "CZK 120.5");
+
+        let (_, format) = CommodityFormat::parse("1,000 EUR").unwrap();
+        assert_eq!(format.thousands_separator, Some(','));
+        assert_eq!(format.precision, 0);
+
+        let (_, format) = CommodityFormat::parse("-5,5 EUR").unwrap();
+        assert_eq!(format.decimal_mark, ',');
+        assert_eq!(format.precision, 1);
+
+        assert!(CommodityFormat::parse("1000").is_none());
+        assert!(CommodityFormat::parse("10 AAPL {$30}").is_none());
+    }
+}
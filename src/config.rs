@@ -0,0 +1,207 @@
+use crate::commodity::CommodityFormats;
+use crate::ledger::{CommodityMatching, JournalOrder};
+use crate::transaction::{SignDisplay, DEBTS_PREFIX};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+
+/// Decimal places split amounts are rounded to, unless configured otherwise.
+pub const DEFAULT_PRECISION: u32 = 2;
+
+/// Direction the amounts of accounts are expected to change in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExpectedSign {
+    Positive,
+    Negative,
+}
+
+/// Settings affecting how transactions are entered and written.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// The ledger executable.
+    pub ledger_binary: String,
+    /// Extra options passed to every ledger invocation.
+    pub ledger_args: Vec<String>,
+    /// Ledger's init file, like `~/.ledgerrc`, passed to every ledger
+    /// invocation.
+    pub ledger_init_file: Option<String>,
+    /// `strftime`-style format of dates typed into transaction headers.
+    pub input_date_format: String,
+    /// `strftime`-style format of dates written into the journal. When not
+    /// set, the format is detected from the journal.
+    pub output_date_format: Option<String>,
+    /// Currency changes use until another one is entered.
+    pub default_currency: Option<String>,
+    /// Whether lines are edited with vi key bindings instead of emacs ones.
+    pub vi_mode: bool,
+    /// File the history of entered lines is kept in.
+    pub history_file: String,
+    /// Whether entered lines are kept out of the history, neither loaded nor
+    /// saved.
+    pub no_history: bool,
+    /// Whether simple and split changes take the amount before the accounts.
+    pub amount_first: bool,
+    /// Whether to start with a transaction dated today, skipping the header.
+    pub quick: bool,
+    /// Whether a header without a date reuses the date of the transaction
+    /// saved last.
+    pub sticky_date: bool,
+    /// Custom keywords of operations, mapped to the built-in ones.
+    pub keywords: HashMap<String, String>,
+    /// Whether to hide the running balance printed after every change.
+    pub quiet: bool,
+    /// Whether to print diagnostics, like the ledger commands run, to stderr.
+    pub verbose: bool,
+    /// Formats of commodities, taking precedence over the ones used in the
+    /// journal.
+    pub commodity_formats: CommodityFormats,
+    /// Decimal places split amounts of commodities are rounded to.
+    pub precisions: HashMap<String, u32>,
+    /// How typed text is matched against commodities by completion.
+    pub commodity_matching: CommodityMatching,
+    /// Alternative names of commodities, like `EUR`, mapped to the names they
+    /// are written as, like `€`.
+    pub commodity_aliases: HashMap<String, String>,
+    /// Short names of accounts, like `g`, mapped to the accounts, like
+    /// `Expenses:Food:Groceries`.
+    pub account_aliases: HashMap<String, String>,
+    /// Whether to ask for confirmation before saving a transaction which
+    /// already is in the journal.
+    pub check_duplicates: bool,
+    /// Whether transactions which don't balance out to zero are refused
+    /// instead of being saved.
+    pub strict_balance: bool,
+    /// Whether to ask for confirmation before saving a transaction with an
+    /// account which was both added to and subtracted from.
+    pub check_opposing_changes: bool,
+    /// Whether to show the diff of the journal and ask for confirmation
+    /// before writing it.
+    pub confirm_diff: bool,
+    /// Account the `ff` command finalizes transactions into.
+    pub finalize_account: Option<String>,
+    /// Order of transactions in the journal. When not set, it is detected
+    /// from the journal.
+    pub order: Option<JournalOrder>,
+    /// Whitespace postings are indented with.
+    pub posting_indent: String,
+    /// Whitespace between the longest account name and its amount.
+    pub amount_separator: String,
+    /// Column at which amounts start. When not set, it is detected from the
+    /// journal.
+    pub amount_column: Option<usize>,
+    /// Whether finished transactions are only printed, never written.
+    pub read_only: bool,
+    /// Whether finished transactions are described as JSON plans of where
+    /// they would be inserted, instead of being written.
+    pub plan_json: bool,
+    /// Whether finished transactions are queued and written together by the
+    /// `save` command, instead of one by one.
+    pub queue_transactions: bool,
+    /// Whether to keep all postings of an account together in the output.
+    pub group_accounts: bool,
+    /// Whether positive amounts are written with a `+` sign.
+    pub sign_display: SignDisplay,
+    /// Whether amounts are padded with trailing zeros to the precision of
+    /// their commodity, both when entered and when written.
+    pub pad_amounts: bool,
+    /// File new transactions are written into, instead of the journal they
+    /// are completed from.
+    pub output_file: Option<String>,
+    /// File listing the accounts offered by completion, instead of the ones
+    /// ledger finds in the journal.
+    pub accounts_file: Option<String>,
+    /// Prefix of the accounts the names of people sharing expenses map to.
+    pub debt_prefix: String,
+    /// Whether split changes tag the transaction with the people sharing
+    /// the amount.
+    pub tag_counterparties: bool,
+    /// Amounts larger than this have to be confirmed before being added.
+    pub max_amount: Option<Decimal>,
+    /// Per-currency limits, taking precedence over `max_amount`.
+    pub currency_max_amounts: HashMap<String, Decimal>,
+    /// Account prefixes, like `Expenses:`, and the direction their accounts
+    /// are expected to change in. Changes in the other one are warned about.
+    pub expected_signs: Vec<(String, ExpectedSign)>,
+    /// Whether errors are printed as `ERROR line=N kind=KIND msg=MESSAGE`
+    /// lines for scripts, instead of friendly messages.
+    pub machine_errors: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            ledger_binary: "ledger".to_owned(),
+            ledger_args: Vec::new(),
+            ledger_init_file: None,
+            input_date_format: "%Y-%m-%d".to_owned(),
+            output_date_format: None,
+            default_currency: None,
+            vi_mode: false,
+            history_file: "history.txt".to_owned(),
+            no_history: false,
+            amount_first: false,
+            quick: false,
+            sticky_date: false,
+            keywords: HashMap::new(),
+            quiet: false,
+            verbose: false,
+            commodity_formats: CommodityFormats::new(),
+            precisions: HashMap::new(),
+            commodity_matching: CommodityMatching::Prefix,
+            commodity_aliases: HashMap::new(),
+            account_aliases: HashMap::new(),
+            check_duplicates: false,
+            strict_balance: false,
+            check_opposing_changes: false,
+            confirm_diff: false,
+            finalize_account: None,
+            order: None,
+            posting_indent: "\t".to_owned(),
+            amount_separator: "  ".to_owned(),
+            amount_column: None,
+            read_only: false,
+            plan_json: false,
+            queue_transactions: false,
+            group_accounts: false,
+            sign_display: SignDisplay::NegativeOnly,
+            pad_amounts: false,
+            output_file: None,
+            accounts_file: None,
+            debt_prefix: DEBTS_PREFIX.to_owned(),
+            tag_counterparties: false,
+            max_amount: None,
+            currency_max_amounts: HashMap::new(),
+            expected_signs: Vec::new(),
+            machine_errors: false,
+        }
+    }
+}
+
+impl Config {
+    /// Returns the largest amount of the currency which can be added without
+    /// confirmation.
+    pub fn max_amount(&self, currency: &str) -> Option<Decimal> {
+        self.currency_max_amounts
+            .get(currency)
+            .copied()
+            .or(self.max_amount)
+    }
+
+    /// Returns the direction the account is expected to change in, given by
+    /// the longest prefix it starts with.
+    pub fn expected_sign(&self, account: &str) -> Option<ExpectedSign> {
+        self.expected_signs
+            .iter()
+            .filter(|(prefix, _)| account.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, sign)| *sign)
+    }
+
+    /// Returns the decimal places split amounts of the currency are rounded
+    /// to.
+    pub fn precision(&self, currency: &str) -> u32 {
+        self.precisions
+            .get(currency)
+            .copied()
+            .unwrap_or(DEFAULT_PRECISION)
+    }
+}
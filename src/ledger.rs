@@ -1,136 +1,1490 @@
-use crate::transaction::Transaction;
-use chrono::NaiveDate;
+use crate::commodity::{CommodityFormat, CommodityFormats};
+use crate::config::Config;
+use crate::transaction::{columns, parse_ledger_print, Amount, Transaction, TransactionDisplay};
+use chrono::{Duration, NaiveDate};
+use lazy_static::lazy_static;
+use regex::Regex;
+use rust_decimal::Decimal;
 use rustyline::error::ReadlineError;
-use std::fs::{rename, File};
-use std::io::{Read, Write};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, rename, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
 use std::process::{Command, Output};
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time;
 
+/// How long a ledger command has to run before a status line is shown.
+const STATUS_DELAY: time::Duration = time::Duration::from_millis(100);
+
+/// Builds a ledger invocation for the given file. Extra arguments are inserted
+/// before the subcommand, so ledger treats them as global options.
+fn ledger_command(path_to_ledger_file: &str, config: &Config) -> Command {
+    let mut command = Command::new(&config.ledger_binary);
+    if let Some(init_file) = &config.ledger_init_file {
+        command.arg("--init-file").arg(init_file);
+    }
+    command
+        .arg("-f")
+        .arg(path_to_ledger_file)
+        .args(&config.ledger_args);
+    command
+}
+
+/// Prints a diagnostic message to stderr when running with `--verbose`.
+fn log(config: &Config, message: impl fmt::Display) {
+    if config.verbose {
+        eprintln!("[splitter] {}", message);
+    }
+}
+
+/// Runs the ledger command, printing a status line while it runs if it takes
+/// longer than `STATUS_DELAY`.
+fn run_ledger(config: &Config, command: &mut Command) -> io::Result<Output> {
+    log(config, format!("running {:?}", command));
+    let (done_sender, done) = mpsc::channel();
+    let status = thread::spawn(move || {
+        if done.recv_timeout(STATUS_DELAY).is_err() {
+            eprint!("\rRunning ledger...");
+            let _ = done.recv();
+            eprint!("\r\x1b[K");
+        }
+    });
+    let out = command.output();
+    let _ = done_sender.send(());
+    let _ = status.join();
+    if let Ok(out) = &out {
+        log(
+            config,
+            format!(
+                "ledger exited with {}, printing {} bytes",
+                out.status,
+                out.stdout.len()
+            ),
+        );
+    }
+    out
+}
+
+/// Returns accounts matching the pattern, read from the accounts file if one
+/// is configured and from the journal otherwise.
 pub fn get_accounts(
     path_to_ledger_file: &str,
+    config: &Config,
     pattern: &str,
 ) -> Result<Vec<String>, ReadlineError> {
-    let out = Command::new("ledger")
-        .arg("-f")
-        .arg(path_to_ledger_file)
-        .arg("accounts")
-        .arg(pattern)
-        .output()?;
-    process_ledger_output(out)
+    if let Some(accounts_file) = &config.accounts_file {
+        let mut contents = String::new();
+        File::open(accounts_file)?.read_to_string(&mut contents)?;
+        return Ok(filter_accounts(&contents, pattern));
+    }
+    let out = run_ledger(
+        config,
+        ledger_command(path_to_ledger_file, config)
+            .arg("accounts")
+            .arg(pattern),
+    )?;
+    process_ledger_output("accounts", out)
+}
+
+/// Returns the accounts listed in an accounts file which contain the pattern,
+/// ignoring case like ledger does. Lines can be bare account names or
+/// `account` directives, whose indented subdirectives and comments are
+/// skipped.
+fn filter_accounts(accounts_file: &str, pattern: &str) -> Vec<String> {
+    let pattern = pattern.to_lowercase();
+    accounts_file
+        .lines()
+        .filter(|line| !line.starts_with(|c: char| c.is_whitespace() || ";#%|*".contains(c)))
+        .map(|line| line.strip_prefix("account ").unwrap_or(line).trim())
+        .filter(|account| !account.is_empty() && account.to_lowercase().contains(&pattern))
+        .map(|account| account.to_owned())
+        .collect()
+}
+
+/// Returns the payees of the journal's transactions starting with the typed
+/// text, ignoring case.
+pub fn get_payees(
+    path_to_ledger_file: &str,
+    config: &Config,
+    typed: &str,
+) -> Result<Vec<String>, ReadlineError> {
+    let out = run_ledger(
+        config,
+        ledger_command(path_to_ledger_file, config).arg("payees"),
+    )?;
+    let typed = typed.to_lowercase();
+    Ok(process_ledger_output("payees", out)?
+        .into_iter()
+        .filter(|payee| payee.to_lowercase().starts_with(&typed))
+        .collect())
+}
+
+pub fn get_balance(
+    path_to_ledger_file: &str,
+    config: &Config,
+    patterns: &[&str],
+) -> Result<Vec<String>, ReadlineError> {
+    let out = run_ledger(
+        config,
+        ledger_command(path_to_ledger_file, config)
+            .arg("balance")
+            .args(patterns),
+    )?;
+    process_ledger_output("balance", out)
 }
 
 pub fn get_commodities(
     path_to_ledger_file: &str,
-    starts_with: &str,
+    config: &Config,
+    typed: &str,
 ) -> Result<Vec<String>, ReadlineError> {
-    let out = Command::new("ledger")
-        .arg("-f")
-        .arg(path_to_ledger_file)
-        .arg("commodities")
-        .output()?;
-    let all_commodities = process_ledger_output(out)?;
-    return Ok(all_commodities
-        .iter()
-        .filter_map(|c| {
-            if c.starts_with(starts_with) {
-                Some(c.to_owned())
-            } else {
-                None
-            }
+    let out = run_ledger(
+        config,
+        ledger_command(path_to_ledger_file, config).arg("commodities"),
+    )?;
+    let all_commodities = process_ledger_output("commodities", out)?;
+    Ok(matching_commodities(
+        all_commodities,
+        typed,
+        config.commodity_matching,
+    ))
+}
+
+/// Returns the commodities matching the typed text. Substrings are matched
+/// regardless of case, since symbols are short and easily mistyped.
+fn matching_commodities(
+    commodities: Vec<String>,
+    typed: &str,
+    matching: CommodityMatching,
+) -> Vec<String> {
+    let typed_lowercase = typed.to_lowercase();
+    commodities
+        .into_iter()
+        .filter(|c| match matching {
+            CommodityMatching::Prefix => c.starts_with(typed),
+            CommodityMatching::Substring => c.to_lowercase().contains(&typed_lowercase),
         })
-        .collect());
+        .collect()
 }
 
-pub fn write_transaction(path_to_ledger_file: &str, tx: &Transaction) -> Result<(), ReadlineError> {
-    let date_ends = get_date_end_positions(path_to_ledger_file)?;
+/// Returns the format of dates written into the ledger file. Unless one is
+/// configured, it uses the same separator as the existing transactions.
+pub fn output_date_format(
+    path_to_ledger_file: &str,
+    config: &Config,
+) -> Result<String, ReadlineError> {
+    if let Some(format) = &config.output_date_format {
+        return Ok(format.clone());
+    }
+    let mut buf: Vec<u8> = Vec::new();
+    File::open(path_to_ledger_file)?.read_to_end(&mut buf)?;
+    Ok(detect_date_format(&buf))
+}
+
+/// Postings at the end of the journal sampled for the column amounts start at.
+const SAMPLED_POSTINGS: usize = 20;
+
+/// Returns the column amounts are written at. Unless one is configured, it's
+/// the one most of the journal's last postings use, or `None` when the
+/// journal has no postings with amounts.
+pub fn amount_column(
+    path_to_ledger_file: &str,
+    config: &Config,
+) -> Result<Option<usize>, ReadlineError> {
+    if config.amount_column.is_some() {
+        return Ok(config.amount_column);
+    }
+    let mut buf: Vec<u8> = Vec::new();
+    File::open(path_to_ledger_file)?.read_to_end(&mut buf)?;
+    Ok(detect_amount_column(&buf))
+}
+
+fn detect_amount_column(journal: &[u8]) -> Option<usize> {
+    let journal = String::from_utf8_lossy(journal);
+    let mut counts: BTreeMap<usize, usize> = BTreeMap::new();
+    for column in journal
+        .lines()
+        .rev()
+        .filter_map(posting_amount_column)
+        .take(SAMPLED_POSTINGS)
+    {
+        *counts.entry(column).or_insert(0) += 1;
+    }
+    // Ties go to the smaller column.
+    counts
+        .into_iter()
+        .max_by(|(a, a_count), (b, b_count)| a_count.cmp(b_count).then(b.cmp(a)))
+        .map(|(column, _)| column)
+}
+
+/// Returns the column the amount of a posting line starts at, or `None` for
+/// lines which aren't postings with an amount.
+fn posting_amount_column(line: &str) -> Option<usize> {
+    let posting = line.trim_start();
+    if posting.len() == line.len() || posting.is_empty() || posting.starts_with([';', '#']) {
+        return None;
+    }
+    let account_end = match (posting.find("  "), posting.find('\t')) {
+        (Some(spaces), Some(tab)) => spaces.min(tab),
+        (spaces, tab) => spaces.or(tab)?,
+    };
+    let amount = posting[account_end..].trim_start();
+    if amount.is_empty() || amount.starts_with(';') {
+        return None;
+    }
+    Some(columns(&line[..line.len() - amount.len()], 0))
+}
+
+fn detect_date_format(journal: &[u8]) -> String {
+    lazy_static! {
+        static ref DATE_RE: Regex = Regex::new("(?m)^\\d{4}([-/.])\\d{1,2}[-/.]\\d{1,2}").unwrap();
+    }
+    let separator = DATE_RE
+        .captures(&String::from_utf8_lossy(journal))
+        .map(|captures| captures[1].to_owned())
+        .unwrap_or_else(|| "/".to_owned());
+    format!("%Y{0}%m{0}%d", separator)
+}
+
+/// Returns the formats of commodities, as ledger writes them in the journal,
+/// overridden by the configured ones.
+pub fn commodity_formats(
+    path_to_ledger_file: &str,
+    config: &Config,
+) -> Result<CommodityFormats, ReadlineError> {
+    let out = run_ledger(
+        config,
+        ledger_command(path_to_ledger_file, config)
+            .arg("register")
+            .arg("--format")
+            .arg("%(amount)\n"),
+    )?;
+    let mut formats = CommodityFormats::new();
+    for sample in process_ledger_output("register", out)? {
+        if let Some((commodity, format)) = CommodityFormat::parse(&sample) {
+            formats.entry(commodity).or_insert(format);
+        }
+    }
+    formats.extend(config.commodity_formats.clone());
+    Ok(formats)
+}
+
+/// Returns the transactions of the journal with the given date and
+/// description, read from `ledger print` so they can be edited again.
+pub fn find_transactions(
+    path_to_ledger_file: &str,
+    config: &Config,
+    date: NaiveDate,
+    description: &str,
+) -> Result<Vec<Transaction>, ReadlineError> {
+    let out = run_ledger(
+        config,
+        ledger_command(path_to_ledger_file, config)
+            .arg("print")
+            .arg("--date-format")
+            .arg("%Y/%m/%d")
+            .arg("--begin")
+            .arg(date.format("%Y/%m/%d").to_string())
+            .arg("--end")
+            .arg((date + Duration::days(1)).format("%Y/%m/%d").to_string()),
+    )?;
+    let printed = process_ledger_output("print", out)?.join("\n");
+    let transactions = parse_ledger_print(&printed).map_err(io::Error::other)?;
+    Ok(transactions
+        .into_iter()
+        .filter(|tx| tx.description == description)
+        .collect())
+}
+
+/// Returns the transactions the journal contains for the given date.
+pub fn transactions_on(
+    path_to_ledger_file: &str,
+    config: &Config,
+    date: NaiveDate,
+) -> Result<Vec<Transaction>, ReadlineError> {
+    let out = run_ledger(
+        config,
+        ledger_command(path_to_ledger_file, config)
+            .arg("register")
+            .arg("--begin")
+            .arg(date.format("%Y/%m/%d").to_string())
+            .arg("--end")
+            .arg((date + Duration::days(1)).format("%Y/%m/%d").to_string())
+            .arg("--format")
+            .arg("%(xact.beg_pos)\t%(payee)\t%(account)\t%(commodity(amount))\t%(quantity(amount))\n"),
+    )?;
+    let mut transactions: BTreeMap<usize, Transaction> = BTreeMap::new();
+    for line in process_ledger_output("register", out)? {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 5 {
+            continue;
+        }
+        let (beg_pos, quantity) = match (fields[0].parse(), Decimal::from_str(fields[4])) {
+            (Ok(beg_pos), Ok(quantity)) => (beg_pos, quantity),
+            _ => continue,
+        };
+        transactions
+            .entry(beg_pos)
+            .or_insert_with(|| Transaction::new(date, fields[1].to_owned()))
+            .add_change(fields[2], Amount(fields[3].to_owned(), quantity));
+    }
+    Ok(transactions.into_values().collect())
+}
+
+/// Returns the default account declared by the last `bucket` (or `A`)
+/// directive in the journal, if there is one.
+pub fn default_account(path_to_ledger_file: &str) -> Result<Option<String>, ReadlineError> {
+    let mut buf: Vec<u8> = Vec::new();
+    File::open(path_to_ledger_file)?.read_to_end(&mut buf)?;
+    Ok(find_default_account(&String::from_utf8_lossy(&buf)))
+}
+
+fn find_default_account(journal: &str) -> Option<String> {
+    journal
+        .lines()
+        .filter_map(|line| {
+            line.strip_prefix("bucket ")
+                .or_else(|| line.strip_prefix("A "))
+                .map(|account| account.split(';').next().unwrap_or("").trim())
+        })
+        .rfind(|account| !account.is_empty())
+        .map(|account| account.to_owned())
+}
+
+/// Inserts the transaction into the ledger file, keeping the journal ordered
+/// by date. Returns the 1-based line number at which the transaction starts.
+pub fn write_transaction(
+    path_to_ledger_file: &str,
+    config: &Config,
+    tx: &TransactionDisplay,
+    confirm: impl Fn(&str) -> bool,
+) -> Result<usize, ReadlineError> {
+    let lines = write_transactions(
+        path_to_ledger_file,
+        config,
+        std::slice::from_ref(tx),
+        confirm,
+    )?;
+    Ok(lines[0])
+}
+
+/// Inserts all the transactions into the ledger file at once, so ledger only
+/// has to find their positions once. Returns the 1-based line numbers at
+/// which the transactions start. When ledger can't parse the result, none of
+/// the transactions are written. With `--confirm-diff`, the diff of the
+/// journal is passed to `confirm` first, and nothing is written unless it
+/// returns true, failing with `ReadlineError::Interrupted`.
+pub fn write_transactions(
+    path_to_ledger_file: &str,
+    config: &Config,
+    txs: &[TransactionDisplay],
+    confirm: impl Fn(&str) -> bool,
+) -> Result<Vec<usize>, ReadlineError> {
+    if config.strict_balance {
+        for tx in txs {
+            check_balanced(tx.transaction())?;
+        }
+    }
+    // Opening the file for appending creates it when it doesn't exist yet,
+    // without touching its contents otherwise.
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path_to_ledger_file)?;
+    let (buf, insertions) = plan_insertions(path_to_ledger_file, config, txs)?;
+    let (journal, tx_lines) = insert_transactions(&buf, &insertions);
+    if config.confirm_diff {
+        let diff = journal_diff(
+            path_to_ledger_file,
+            &String::from_utf8_lossy(&buf),
+            &String::from_utf8_lossy(&journal),
+        );
+        if !confirm(&diff) {
+            return Err(ReadlineError::Interrupted);
+        }
+    }
+
+    replace_journal(path_to_ledger_file, config, &buf, &journal)?;
+    let written = fs::metadata(path_to_ledger_file)?.len() as usize;
+    let txs: Vec<&str> = insertions.iter().map(|(_, tx)| tx.as_str()).collect();
+    if let Some(warning) = unexpected_growth(buf.len(), written, &txs) {
+        eprintln!("Warning: {} {}", path_to_ledger_file, warning);
+    }
+    Ok(tx_lines)
+}
+
+/// Describes where `write_transaction` would insert the transaction, as a
+/// JSON object with the file, the byte offset, the 1-based line and the text
+/// of the transaction. The journal isn't modified, not even created when it
+/// doesn't exist.
+pub fn plan_transaction(
+    path_to_ledger_file: &str,
+    config: &Config,
+    tx: &TransactionDisplay,
+) -> Result<String, ReadlineError> {
+    let (buf, insertions) = if Path::new(path_to_ledger_file).exists() {
+        plan_insertions(path_to_ledger_file, config, std::slice::from_ref(tx))?
+    } else {
+        (Vec::new(), vec![(Insertion::After(0), tx.to_string())])
+    };
+    let (_, tx_lines) = insert_transactions(&buf, &insertions);
+    let (insertion, tx) = &insertions[0];
+    Ok(format_plan(
+        path_to_ledger_file,
+        split_position(&buf, *insertion),
+        tx_lines[0],
+        tx,
+    ))
+}
+
+fn format_plan(path: &str, offset: usize, line: usize, tx: &str) -> String {
+    format!(
+        "{{\"file\": {}, \"offset\": {}, \"line\": {}, \"transaction\": {}}}",
+        json_string(path),
+        offset,
+        line,
+        json_string(tx)
+    )
+}
+
+/// Quotes the text as a JSON string.
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Contents of a journal and the transactions to insert into it.
+type InsertionPlan = (Vec<u8>, Vec<(Insertion, String)>);
+
+/// Reads the journal and finds where the transactions go in it.
+fn plan_insertions(
+    path_to_ledger_file: &str,
+    config: &Config,
+    txs: &[TransactionDisplay],
+) -> Result<InsertionPlan, ReadlineError> {
+    validate_journal(path_to_ledger_file, config)?;
+    let positions = get_transaction_positions(path_to_ledger_file, config)?;
     let mut buf: Vec<u8> = Vec::new();
     {
         let mut file = File::open(path_to_ledger_file)?;
         file.read_to_end(&mut buf)?;
     }
 
-    let tx_pos = get_pos_for_date(date_ends, tx.date);
-    let split_offset = if tx_pos < buf.len() - 1 { 1 } else { 0 };
+    let order = config.order.unwrap_or_else(|| detect_order(&positions));
+    log(
+        config,
+        format!(
+            "found {} transactions in {}, ordered {:?}",
+            positions.len(),
+            path_to_ledger_file,
+            order
+        ),
+    );
+    let insertions: Vec<(Insertion, String)> = txs
+        .iter()
+        .map(|tx| {
+            let insertion = get_insertion(&positions, order, tx.date());
+            log(
+                config,
+                format!(
+                    "inserting {} {:?}, at byte {}",
+                    tx.date(),
+                    insertion,
+                    split_position(&buf, insertion)
+                ),
+            );
+            (insertion, tx.to_string())
+        })
+        .collect();
+    Ok((buf, insertions))
+}
 
-    let (before_tx, after_tx) = buf.split_at(tx_pos + split_offset);
-    let tmpfile_path = format!("{}.tmp", path_to_ledger_file);
-    let mut tmpfile = File::create(&tmpfile_path)?;
-    tmpfile.write_all(&before_tx)?;
-    if let Some(last_char) = before_tx.last() {
-        if *last_char != 10 || after_tx.len() == 0 {
-            writeln!(tmpfile, "")?;
+/// Unchanged lines shown around the changed ones in diffs.
+const DIFF_CONTEXT: usize = 3;
+
+/// Describes the change of the journal as a unified diff. Transactions are
+/// only ever inserted, so the lines of the original journal are matched in
+/// order with the first equal lines of the new one, the rest were added.
+fn journal_diff(path: &str, before: &str, after: &str) -> String {
+    let before: Vec<&str> = before.lines().collect();
+    let after: Vec<&str> = after.lines().collect();
+    let mut lines: Vec<(char, &str)> = Vec::new();
+    let mut old = 0;
+    for line in &after {
+        if old < before.len() && before[old] == *line {
+            lines.push((' ', line));
+            old += 1;
+        } else {
+            lines.push(('+', line));
+        }
+    }
+    lines.extend(before[old..].iter().map(|line| ('-', *line)));
+
+    let mut diff = format!("--- {0}\n+++ {0}\n", path);
+    let changed: Vec<usize> = (0..lines.len()).filter(|i| lines[*i].0 != ' ').collect();
+    let mut i = 0;
+    while i < changed.len() {
+        let start = changed[i].saturating_sub(DIFF_CONTEXT);
+        let mut end = changed[i] + 1;
+        // Changes closer than twice the context share a hunk.
+        while i < changed.len() && changed[i] <= end + 2 * DIFF_CONTEXT {
+            end = changed[i] + 1;
+            i += 1;
+        }
+        let end = (end + DIFF_CONTEXT).min(lines.len());
+        let old_start = lines[..start].iter().filter(|(op, _)| *op != '+').count();
+        let new_start = lines[..start].iter().filter(|(op, _)| *op != '-').count();
+        let hunk = &lines[start..end];
+        diff.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            old_start + 1,
+            hunk.iter().filter(|(op, _)| *op != '+').count(),
+            new_start + 1,
+            hunk.iter().filter(|(op, _)| *op != '-').count()
+        ));
+        for (op, line) in hunk {
+            diff.push_str(&format!("{}{}\n", op, line));
         }
     }
+    diff
+}
 
-    write!(tmpfile, "{}", tx)?;
+/// Checks that the journal grew by the length of the inserted transactions
+/// and the blank lines separating them, up to three newlines for each.
+/// Describes the difference otherwise, since the insertion must have gone
+/// wrong.
+fn unexpected_growth(before: usize, after: usize, txs: &[&str]) -> Option<String> {
+    let expected: usize = txs.iter().map(|tx| tx.len()).sum();
+    let separators = 3 * txs.len();
+    if after >= before + expected && after <= before + expected + separators {
+        return None;
+    }
+    Some(format!(
+        "changed by {} bytes instead of growing by {} to {}, check it for damage",
+        after as i64 - before as i64,
+        expected,
+        expected + separators
+    ))
+}
+
+/// Fails with the amounts which remain when the transaction doesn't balance
+/// out to zero.
+pub fn check_balanced(tx: &Transaction) -> Result<(), ReadlineError> {
+    if tx.is_balanced(None) {
+        return Ok(());
+    }
+    let unbalanced: Vec<String> = tx
+        .unbalanced()
+        .iter()
+        .map(|amount| amount.to_string())
+        .collect();
+    Err(ReadlineError::Io(io::Error::other(format!(
+        "{} {} doesn't balance, {} remaining",
+        tx.date,
+        tx.description,
+        unbalanced.join(", ")
+    ))))
+}
 
-    if after_tx.first().is_none() || *after_tx.first().unwrap() != 10 {
-        writeln!(tmpfile, "")?;
+/// Removes a transaction written by `write_transaction`, given its text and
+/// the line it was written at. The transaction is also looked for in the rest
+/// of the journal, in case lines were added before it since.
+pub fn remove_transaction(
+    path_to_ledger_file: &str,
+    config: &Config,
+    tx: &str,
+    tx_line: usize,
+) -> Result<(), ReadlineError> {
+    let mut buf: Vec<u8> = Vec::new();
+    {
+        let mut file = File::open(path_to_ledger_file)?;
+        file.read_to_end(&mut buf)?;
     }
+    let journal = cut_transaction(&buf, tx, tx_line).ok_or_else(|| {
+        ReadlineError::Io(io::Error::other(format!(
+            "the transaction is no longer in {}",
+            path_to_ledger_file
+        )))
+    })?;
+    replace_journal(path_to_ledger_file, config, &buf, &journal)
+}
 
-    tmpfile.write_all(&after_tx)?;
+/// Replaces the contents of the journal, restoring the original ones if
+/// ledger can't parse the new journal.
+fn replace_journal(
+    path_to_ledger_file: &str,
+    config: &Config,
+    original: &[u8],
+    journal: &[u8],
+) -> Result<(), ReadlineError> {
+    let tmpfile_path = format!("{}.tmp", path_to_ledger_file);
+    let mut tmpfile = File::create(&tmpfile_path)?;
+    tmpfile.write_all(journal)?;
     tmpfile.sync_all()?;
-    rename(tmpfile_path, path_to_ledger_file)?;
+    rename(&tmpfile_path, path_to_ledger_file)?;
+
+    if let Err(err) = validate_journal(path_to_ledger_file, config) {
+        let mut tmpfile = File::create(&tmpfile_path)?;
+        tmpfile.write_all(original)?;
+        tmpfile.sync_all()?;
+        rename(&tmpfile_path, path_to_ledger_file)?;
+        return Err(ReadlineError::Io(io::Error::other(format!(
+            "{}, the original journal was restored",
+            err
+        ))));
+    }
     Ok(())
 }
 
-fn get_pos_for_date(date_ends: Vec<(NaiveDate, usize)>, tx_date: NaiveDate) -> usize {
-    match date_ends.binary_search_by_key(&tx_date, |(date, _)| *date) {
-        Ok(last_occurrence_index) => date_ends[last_occurrence_index].1,
-        Err(larger_occurrence_index) => {
-            if larger_occurrence_index == 0 {
-                0
-            } else {
-                date_ends[larger_occurrence_index - 1].1
-            }
+/// Returns the byte offset at which the journal is split for the insertion.
+/// Insertions after a transaction go after the end of the line its last
+/// posting is on, insertions before one at the beginning of the line it
+/// starts on. Offsets reported by ledger which don't match the journal, like
+/// with CRLF line endings, thus still never split a line.
+fn split_position(journal: &[u8], insertion: Insertion) -> usize {
+    match insertion {
+        Insertion::After(tx_pos) => {
+            let tx_pos = tx_pos.min(journal.len());
+            journal[tx_pos..]
+                .iter()
+                .position(|c| *c == 10)
+                .map_or(journal.len(), |newline| tx_pos + newline + 1)
         }
+        Insertion::Before(tx_pos) => journal[..tx_pos.min(journal.len())]
+            .iter()
+            .rposition(|c| *c == 10)
+            .map_or(0, |newline| newline + 1),
     }
 }
 
-fn get_date_end_positions(
-    path_to_ledger_file: &str,
-) -> Result<Vec<(NaiveDate, usize)>, ReadlineError> {
-    let out = Command::new("ledger")
-        .arg("-f")
-        .arg(path_to_ledger_file)
-        .arg("register")
-        .arg("--sort")
-        .arg("date,beg_pos")
-        .arg("--format")
-        .arg("%(date),%(end_pos)\n")
-        .output()?;
-    let last_positions = process_ledger_output(out)?
+/// Inserts all the transactions into the journal, returning the lines they
+/// start at. Transactions going to the same place keep their order.
+fn insert_transactions(journal: &[u8], txs: &[(Insertion, String)]) -> (Vec<u8>, Vec<usize>) {
+    let mut splits: Vec<(usize, usize)> = txs
         .iter()
-        .map(|line| {
-            let mut split = line.split(",");
-            let date_str = split.next().unwrap();
-            let end_pos_str = split.next().unwrap();
-            (
-                NaiveDate::parse_from_str(date_str, "%Y/%m/%d").unwrap(),
-                end_pos_str.parse().unwrap(),
-            )
-        })
-        .fold(
-            Vec::new(),
-            |mut collected: Vec<(NaiveDate, usize)>, (date, end_pos)| {
-                if collected.last().is_some() && collected.last().unwrap().0 == date {
-                    collected.pop();
-                    collected.push((date, end_pos));
-                } else {
-                    collected.push((date, end_pos));
-                }
-                collected
+        .enumerate()
+        .map(|(i, (insertion, _))| (split_position(journal, *insertion), i))
+        .collect();
+    splits.sort();
+    // Inserting from the end keeps the positions of earlier insertions valid.
+    let mut result = journal.to_vec();
+    let mut tx_lines = vec![0; txs.len()];
+    let mut inserted: Vec<usize> = Vec::new();
+    for (split_pos, i) in splits.into_iter().rev() {
+        let lines_before = result.iter().filter(|c| **c == 10).count();
+        let (with_tx, tx_line) =
+            insert_transaction(&result, Insertion::Before(split_pos), &txs[i].1);
+        let added_lines = with_tx.iter().filter(|c| **c == 10).count() - lines_before;
+        for j in &inserted {
+            tx_lines[*j] += added_lines;
+        }
+        tx_lines[i] = tx_line;
+        inserted.push(i);
+        result = with_tx;
+    }
+    (result, tx_lines)
+}
+
+/// Returns the journal with the transaction inserted, together with the
+/// 1-based line number at which the transaction starts.
+fn insert_transaction(journal: &[u8], insertion: Insertion, tx: &str) -> (Vec<u8>, usize) {
+    let (before_tx, after_tx) = journal.split_at(split_position(journal, insertion));
+    let mut result = before_tx.to_vec();
+    if !result.is_empty() {
+        if !result.ends_with(b"\n") {
+            result.push(10);
+        }
+        if !result.ends_with(b"\n\n") {
+            result.push(10);
+        }
+    }
+    let tx_line = result.iter().filter(|c| **c == 10).count() + 1;
+
+    result.extend_from_slice(tx.as_bytes());
+
+    if !after_tx.is_empty() && after_tx.first() != Some(&10) {
+        result.push(10);
+    }
+
+    result.extend_from_slice(after_tx);
+    (result, tx_line)
+}
+
+/// Returns the journal without the transaction, undoing `insert_transaction`,
+/// or `None` if the journal doesn't contain the transaction.
+fn cut_transaction(journal: &[u8], tx: &str, tx_line: usize) -> Option<Vec<u8>> {
+    let tx = tx.as_bytes();
+    let at_line = match tx_line.checked_sub(2) {
+        Some(newlines) => journal
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| **c == 10)
+            .nth(newlines)
+            .map_or(journal.len(), |(pos, _)| pos + 1),
+        None => 0,
+    };
+    let mut start = if journal[at_line..].starts_with(tx) {
+        at_line
+    } else {
+        journal.windows(tx.len()).position(|window| window == tx)?
+    };
+    let mut end = start + tx.len();
+    // Removes the blank line separating the transaction from the journal.
+    if journal.get(end) == Some(&10) {
+        end += 1;
+    } else if start >= 2 && &journal[start - 2..start] == b"\n\n" {
+        start -= 1;
+    }
+    let mut result = journal[..start].to_vec();
+    result.extend_from_slice(&journal[end..]);
+    Some(result)
+}
+
+/// Checks that ledger can parse the journal, returning ledger's error if it
+/// can't.
+fn validate_journal(path_to_ledger_file: &str, config: &Config) -> Result<(), ReadlineError> {
+    let out = run_ledger(
+        config,
+        ledger_command(path_to_ledger_file, config).arg("balance"),
+    )?;
+    if out.status.success() {
+        Ok(())
+    } else {
+        Err(ReadlineError::Io(io::Error::other(format!(
+            "ledger can't parse {}: {}",
+            path_to_ledger_file,
+            String::from_utf8_lossy(&out.stderr).trim()
+        ))))
+    }
+}
+
+/// Order of transactions in the journal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JournalOrder {
+    Ascending,
+    Descending,
+}
+
+/// How typed text is matched against the commodities offered by completion.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommodityMatching {
+    Prefix,
+    Substring,
+}
+
+/// Position of a transaction in the journal, in bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct TxPosition {
+    date: NaiveDate,
+    beg_pos: usize,
+    end_pos: usize,
+}
+
+/// Where the new transaction goes: after the end of a transaction or before
+/// the beginning of one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Insertion {
+    After(usize),
+    Before(usize),
+}
+
+/// Journals are considered newest-first if their first transaction is newer
+/// than their last one.
+fn detect_order(positions: &[TxPosition]) -> JournalOrder {
+    match (positions.first(), positions.last()) {
+        (Some(first), Some(last)) if first.date > last.date => JournalOrder::Descending,
+        _ => JournalOrder::Ascending,
+    }
+}
+
+fn get_insertion(positions: &[TxPosition], order: JournalOrder, tx_date: NaiveDate) -> Insertion {
+    match order {
+        JournalOrder::Ascending => match get_pos_for_date(get_date_ends(positions), tx_date) {
+            Some(end_pos) => Insertion::After(end_pos),
+            // A transaction older than all the others goes before the first
+            // one, after the directives the journal may start with.
+            None => match positions.first() {
+                Some(first) => Insertion::Before(first.beg_pos),
+                None => Insertion::After(0),
             },
-        );
-    Ok(last_positions)
+        },
+        // The new transaction goes before the first one which isn't newer.
+        JournalOrder::Descending => match positions.iter().find(|p| p.date <= tx_date) {
+            Some(position) => Insertion::Before(position.beg_pos),
+            None => Insertion::After(positions.last().map_or(0, |p| p.end_pos)),
+        },
+    }
+}
+
+/// Returns the end of the last transaction which isn't newer than the date,
+/// or `None` when all of them are.
+fn get_pos_for_date(date_ends: Vec<(NaiveDate, usize)>, tx_date: NaiveDate) -> Option<usize> {
+    match date_ends.binary_search_by_key(&tx_date, |(date, _)| *date) {
+        Ok(last_occurrence_index) => Some(date_ends[last_occurrence_index].1),
+        Err(0) => None,
+        Err(larger_occurrence_index) => Some(date_ends[larger_occurrence_index - 1].1),
+    }
+}
+
+/// Returns the end of the last transaction of each date, sorted by date.
+fn get_date_ends(positions: &[TxPosition]) -> Vec<(NaiveDate, usize)> {
+    let mut sorted = positions.to_vec();
+    sorted.sort_by_key(|p| (p.date, p.beg_pos));
+    sorted.into_iter().fold(
+        Vec::new(),
+        |mut collected: Vec<(NaiveDate, usize)>, position| {
+            if collected.last().is_some() && collected.last().unwrap().0 == position.date {
+                collected.pop();
+            }
+            collected.push((position.date, position.end_pos));
+            collected
+        },
+    )
+}
+
+/// Returns the positions of transactions in the order they are in the file.
+fn get_transaction_positions(
+    path_to_ledger_file: &str,
+    config: &Config,
+) -> Result<Vec<TxPosition>, ReadlineError> {
+    let out = run_ledger(
+        config,
+        ledger_command(path_to_ledger_file, config)
+            .arg("register")
+            .arg("--date-format")
+            .arg("%Y/%m/%d")
+            .arg("--format")
+            .arg("%(date),%(xact.beg_pos),%(end_pos)\n"),
+    )?;
+    fold_positions(&process_ledger_output("register", out)?)
+}
+
+/// Folds the register rows into one position per transaction. Rows are keyed
+/// by the transaction's beginning, since postings ledger adds implicitly
+/// (like the ones balancing against a `bucket` account) don't have to be
+/// adjacent to the rest of their transaction, nor end where it does.
+fn fold_positions(lines: &[String]) -> Result<Vec<TxPosition>, ReadlineError> {
+    let mut positions: BTreeMap<usize, TxPosition> = BTreeMap::new();
+    for line in lines {
+        let position = parse_position(line).ok_or_else(|| {
+            ReadlineError::Io(io::Error::other(format!(
+                "can't read transaction position from ledger register: {}",
+                line
+            )))
+        })?;
+        positions
+            .entry(position.beg_pos)
+            .and_modify(|p| p.end_pos = p.end_pos.max(position.end_pos))
+            .or_insert(position);
+    }
+    Ok(positions.into_values().collect())
 }
 
-fn process_ledger_output(out: Output) -> Result<Vec<String>, ReadlineError> {
-    match String::from_utf8(out.stdout) {
-        Ok(str_output) => Ok(str_output
-            .split('\n')
-            .filter(|s| s != &"")
-            .map(|s| s.to_owned())
-            .collect()),
-        Err(_) => Err(ReadlineError::Utf8Error),
+/// Parses a register row of the date and the positions of a posting.
+fn parse_position(line: &str) -> Option<TxPosition> {
+    let mut split = line.split(',');
+    Some(TxPosition {
+        date: NaiveDate::parse_from_str(split.next()?, "%Y/%m/%d").ok()?,
+        beg_pos: split.next()?.parse().ok()?,
+        end_pos: split.next()?.parse().ok()?,
+    })
+}
+
+/// Splits the output of the ledger subcommand into lines. Journals with
+/// invalid UTF-8 would otherwise break completion altogether, so invalid
+/// bytes are replaced, with a warning. Fails with ledger's error when it
+/// exits unsuccessfully, like when the journal can't be parsed, instead of
+/// taking the missing output for an empty journal.
+fn process_ledger_output(subcommand: &str, out: Output) -> Result<Vec<String>, ReadlineError> {
+    if !out.status.success() {
+        return Err(ReadlineError::Io(io::Error::other(format!(
+            "ledger {} failed ({}): {}",
+            subcommand,
+            out.status,
+            String::from_utf8_lossy(&out.stderr).trim()
+        ))));
+    }
+    let output = match String::from_utf8(out.stdout) {
+        Ok(output) => output,
+        Err(err) => {
+            eprintln!(
+                "Warning: ledger {} printed invalid UTF-8, replacing the invalid bytes",
+                subcommand
+            );
+            String::from_utf8_lossy(err.as_bytes()).into_owned()
+        }
+    };
+    Ok(output
+        .split('\n')
+        .filter(|s| s != &"")
+        .map(|s| s.to_owned())
+        .collect())
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use rust_decimal_macros::*;
+
+    #[allow(dead_code)]
+    fn positions(dates: &[(i32, u32, u32)]) -> Vec<TxPosition> {
+        dates
+            .iter()
+            .enumerate()
+            .map(|(i, (y, m, d))| TxPosition {
+                date: NaiveDate::from_ymd(*y, *m, *d),
+                beg_pos: i * 100,
+                end_pos: i * 100 + 90,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ascending_insertion() {
+        let positions = positions(&[(2020, 1, 1), (2020, 2, 1), (2020, 2, 1), (2020, 3, 1)]);
+        assert_eq!(detect_order(&positions), JournalOrder::Ascending);
+        let insertion = |d| get_insertion(&positions, JournalOrder::Ascending, d);
+        assert_eq!(
+            insertion(NaiveDate::from_ymd(2020, 2, 1)),
+            Insertion::After(290)
+        );
+        assert_eq!(
+            insertion(NaiveDate::from_ymd(2020, 2, 15)),
+            Insertion::After(290)
+        );
+        assert_eq!(
+            insertion(NaiveDate::from_ymd(2020, 4, 1)),
+            Insertion::After(390)
+        );
+        assert_eq!(
+            insertion(NaiveDate::from_ymd(2020, 1, 15)),
+            Insertion::After(90)
+        );
+    }
+
+    #[test]
+    fn descending_insertion() {
+        let positions = positions(&[(2020, 3, 1), (2020, 2, 1), (2020, 2, 1), (2020, 1, 1)]);
+        assert_eq!(detect_order(&positions), JournalOrder::Descending);
+        let insertion = |d| get_insertion(&positions, JournalOrder::Descending, d);
+        assert_eq!(
+            insertion(NaiveDate::from_ymd(2020, 4, 1)),
+            Insertion::Before(0)
+        );
+        assert_eq!(
+            insertion(NaiveDate::from_ymd(2020, 2, 1)),
+            Insertion::Before(100)
+        );
+        assert_eq!(
+            insertion(NaiveDate::from_ymd(2020, 2, 15)),
+            Insertion::Before(100)
+        );
+        assert_eq!(
+            insertion(NaiveDate::from_ymd(2020, 1, 15)),
+            Insertion::Before(300)
+        );
+        assert_eq!(
+            insertion(NaiveDate::from_ymd(2019, 12, 1)),
+            Insertion::After(390)
+        );
+    }
+
+    #[test]
+    fn same_date_as_newest() {
+        let journal = "2020/03/01 Lidl\n    Expenses:Food  EUR 5\n    Assets:Cash\n\n\
+                       2020/03/02 Tesco\n    Expenses:Food  EUR 3\n    Assets:Cash\n\n\
+                       2020/03/02 Billa\n    Expenses:Food  EUR 2\n    Assets:Cash\n";
+        let tx = "2020/03/02 Albert\n    Expenses:Food  EUR 1\n    Assets:Cash\n";
+        let positions = vec![
+            TxPosition {
+                date: NaiveDate::from_ymd(2020, 3, 2),
+                beg_pos: journal.find("2020/03/02 Tesco").unwrap(),
+                end_pos: journal.find("\n\n2020/03/02 Billa").unwrap(),
+            },
+            TxPosition {
+                date: NaiveDate::from_ymd(2020, 3, 2),
+                beg_pos: journal.find("2020/03/02 Billa").unwrap(),
+                end_pos: journal.len() - 1,
+            },
+        ];
+        let insertion = get_insertion(
+            &positions,
+            JournalOrder::Ascending,
+            NaiveDate::from_ymd(2020, 3, 2),
+        );
+        assert_eq!(insertion, Insertion::After(journal.len() - 1));
+        let (result, line) = insert_transaction(journal.as_bytes(), insertion, tx);
+        assert_eq!(
+            String::from_utf8(result).unwrap(),
+            format!("{}\n{}", journal, tx)
+        );
+        assert_eq!(line, 13);
+
+        let directives = "\nP 2020/03/02 EUR 25 CZK\ncommodity EUR\n";
+        let with_directives = format!("{}{}", journal, directives);
+        let (result, line) = insert_transaction(with_directives.as_bytes(), insertion, tx);
+        assert_eq!(
+            String::from_utf8(result).unwrap(),
+            format!("{}\n{}{}", journal, tx, directives)
+        );
+        assert_eq!(line, 13);
+
+        let directives = "P 2020/03/02 EUR 25 CZK\n";
+        let with_directives = format!("{}{}", journal, directives);
+        let (result, line) = insert_transaction(with_directives.as_bytes(), insertion, tx);
+        assert_eq!(
+            String::from_utf8(result).unwrap(),
+            format!("{}\n{}\n{}", journal, tx, directives)
+        );
+        assert_eq!(line, 13);
+    }
+
+    #[test]
+    fn leading_directives() {
+        let journal = "account Assets:Cash\ncommodity EUR\n\n\
+                       2020/03/01 Lidl\n    Expenses:Food  EUR 5\n    Assets:Cash\n";
+        let tx = "2020/02/01 Albert\n    Expenses:Food  EUR 1\n    Assets:Cash\n";
+        let positions = vec![TxPosition {
+            date: NaiveDate::from_ymd(2020, 3, 1),
+            beg_pos: journal.find("2020/03/01").unwrap(),
+            end_pos: journal.len() - 1,
+        }];
+        let insertion = get_insertion(
+            &positions,
+            JournalOrder::Ascending,
+            NaiveDate::from_ymd(2020, 2, 1),
+        );
+        assert_eq!(insertion, Insertion::Before(positions[0].beg_pos));
+        let (result, line) = insert_transaction(journal.as_bytes(), insertion, tx);
+        let (directives, lidl) = journal.split_at(positions[0].beg_pos);
+        assert_eq!(
+            String::from_utf8(result).unwrap(),
+            format!("{}{}\n{}", directives, tx, lidl)
+        );
+        assert_eq!(line, 4);
+        assert_eq!(
+            get_insertion(
+                &[],
+                JournalOrder::Ascending,
+                NaiveDate::from_ymd(2020, 2, 1)
+            ),
+            Insertion::After(0)
+        );
+    }
+
+    #[test]
+    fn mid_line_offsets() {
+        let journal = "2020/03/01 Lidl\n    Expenses:Food  EUR 5\n    Assets:Cash\n\n\
+                       2020/03/03 Billa\n    Expenses:Food  EUR 2\n    Assets:Cash\n";
+        let tx = "2020/03/02 Albert\n    Expenses:Food  EUR 1\n    Assets:Cash\n";
+        let billa = journal.find("2020/03/03").unwrap();
+        let in_lidl_posting = journal.find("EUR 5").unwrap();
+        assert_eq!(
+            split_position(journal.as_bytes(), Insertion::After(in_lidl_posting)),
+            journal.find("    Assets:Cash").unwrap()
+        );
+        assert_eq!(split_position(journal.as_bytes(), Insertion::Before(5)), 0);
+        assert_eq!(
+            split_position(journal.as_bytes(), Insertion::After(journal.len() + 10)),
+            journal.len()
+        );
+
+        let (lidl, rest) = journal.split_at(billa);
+        let (result, line) =
+            insert_transaction(journal.as_bytes(), Insertion::Before(billa + 4), tx);
+        assert_eq!(
+            String::from_utf8(result).unwrap(),
+            format!("{}{}\n{}", lidl, tx, rest)
+        );
+        assert_eq!(line, 5);
+    }
+
+    #[test]
+    fn journal_diffs() {
+        let journal = "\
+2020/03/01 Lidl
+    Expenses:Food  EUR 5
+    Assets:Cash
+
+2020/03/03 Billa
+    Expenses:Food  EUR 2
+    Assets:Cash
+";
+        let tx = "2020/03/02 Albert\n    Expenses:Food  EUR 1\n    Assets:Cash\n";
+        let insertions = [(
+            Insertion::After(journal.find("\n\n").unwrap()),
+            tx.to_owned(),
+        )];
+        let (with_tx, _) = insert_transactions(journal.as_bytes(), &insertions);
+        assert_eq!(
+            journal_diff(
+                "journal.ledger",
+                journal,
+                &String::from_utf8(with_tx).unwrap()
+            ),
+            "--- journal.ledger\n\
+             +++ journal.ledger\n\
+             @@ -2,6 +2,10 @@\n     \
+             Expenses:Food  EUR 5\n     \
+             Assets:Cash\n \n\
+             +2020/03/02 Albert\n\
+             +    Expenses:Food  EUR 1\n\
+             +    Assets:Cash\n\
+             +\n \
+             2020/03/03 Billa\n     \
+             Expenses:Food  EUR 2\n     \
+             Assets:Cash\n"
+        );
+
+        let before: Vec<String> = (1..=20).map(|i| format!("line {}", i)).collect();
+        let mut after = before.clone();
+        after.insert(15, "added 2".to_owned());
+        after.insert(2, "added 1".to_owned());
+        assert_eq!(
+            journal_diff("j", &before.join("\n"), &after.join("\n")),
+            "--- j\n+++ j\n\
+             @@ -1,5 +1,6 @@\n line 1\n line 2\n+added 1\n line 3\n line 4\n line 5\n\
+             @@ -13,6 +14,7 @@\n line 13\n line 14\n line 15\n+added 2\n line 16\n line 17\n line 18\n"
+        );
+    }
+
+    #[test]
+    fn journal_growth() {
+        let txs = [
+            "2020/03/02 Albert\n    Expenses:Food  EUR 1\n    Assets:Cash\n",
+            "2020/03/03 Billa\n    Expenses:Food  EUR 2\n    Assets:Cash\n",
+        ];
+        let length = txs[0].len() + txs[1].len();
+        assert_eq!(unexpected_growth(100, 100 + length, &txs), None);
+        assert_eq!(unexpected_growth(100, 100 + length + 6, &txs), None);
+        assert_eq!(
+            unexpected_growth(100, 40, &txs).unwrap(),
+            format!(
+                "changed by -60 bytes instead of growing by {} to {}, check it for damage",
+                length,
+                length + 6
+            )
+        );
+        assert!(unexpected_growth(100, 100 + length + 7, &txs).is_some());
+        assert!(unexpected_growth(100, 100 + length - 1, &txs).is_some());
+    }
+
+    #[test]
+    fn insertion_plan() {
+        assert_eq!(
+            format_plan(
+                "C:\\ledger\\\"main\".ledger",
+                52,
+                4,
+                "2020/03/02 Albert\n\tExpenses:Food  € 1\n\tAssets:Cash\n"
+            ),
+            "{\"file\": \"C:\\\\ledger\\\\\\\"main\\\".ledger\", \"offset\": 52, \"line\": 4, \
+             \"transaction\": \"2020/03/02 Albert\\n\\tExpenses:Food  € 1\\n\\tAssets:Cash\\n\"}"
+        );
+        assert_eq!(json_string("bell\u{7}"), "\"bell\\u0007\"");
+    }
+
+    #[test]
+    fn batch_insertion() {
+        let journal = "2020/03/01 Lidl\n    Expenses:Food  EUR 5\n    Assets:Cash\n\n\
+                       2020/03/03 Billa\n    Expenses:Food  EUR 2\n    Assets:Cash\n";
+        let tx = |description: &str| {
+            format!(
+                "2020/03/02 {}\n    Expenses:Food  EUR 1\n    Assets:Cash\n",
+                description
+            )
+        };
+        let middle = Insertion::After(journal.find("\n\n").unwrap());
+        let end = Insertion::After(journal.len() - 1);
+        let txs = vec![
+            (end, tx("Albert")),
+            (middle, tx("Tesco")),
+            (middle, tx("Penny")),
+        ];
+        let (result, lines) = insert_transactions(journal.as_bytes(), &txs);
+        let (lidl, billa) = journal.split_at(journal.find("2020/03/03").unwrap());
+        assert_eq!(
+            String::from_utf8(result.clone()).unwrap(),
+            format!(
+                "{}{}\n{}\n{}\n{}",
+                lidl,
+                tx("Tesco"),
+                tx("Penny"),
+                billa,
+                tx("Albert")
+            )
+        );
+        assert_eq!(lines, vec![17, 5, 9]);
+        let result_lines: Vec<&str> = std::str::from_utf8(&result).unwrap().lines().collect();
+        assert_eq!(result_lines[16], "2020/03/02 Albert");
+        assert_eq!(result_lines[4], "2020/03/02 Tesco");
+        assert_eq!(result_lines[8], "2020/03/02 Penny");
+    }
+
+    #[test]
+    fn transaction_removal() {
+        let journal = "2020/03/01 Lidl\n    Expenses:Food  EUR 5\n    Assets:Cash\n\n\
+                       2020/03/03 Billa\n    Expenses:Food  EUR 2\n    Assets:Cash\n";
+        let tx = "2020/03/02 Albert\n    Expenses:Food  EUR 1\n    Assets:Cash\n";
+        let insertions = [
+            Insertion::Before(0),
+            Insertion::After(journal.find("\n\n").unwrap()),
+            Insertion::After(journal.len() - 1),
+        ];
+        for insertion in insertions.iter() {
+            let (inserted, line) = insert_transaction(journal.as_bytes(), *insertion, tx);
+            let removed = cut_transaction(&inserted, tx, line).unwrap();
+            assert_eq!(String::from_utf8(removed).unwrap(), journal);
+            let moved = format!("; Comment\n{}", String::from_utf8(inserted).unwrap());
+            let removed = cut_transaction(moved.as_bytes(), tx, line).unwrap();
+            assert_eq!(
+                String::from_utf8(removed).unwrap(),
+                format!("; Comment\n{}", journal)
+            );
+        }
+        assert!(cut_transaction(journal.as_bytes(), tx, 1).is_none());
+    }
+
+    #[test]
+    fn implicit_postings() {
+        let lines: Vec<String> = [
+            "2020/03/01,0,40",
+            "2020/03/02,60,110",
+            "2020/03/01,0,0",
+            "2020/03/02,60,90",
+            "2020/03/02,60,0",
+        ]
+        .iter()
+        .map(|line| line.to_string())
+        .collect();
+        assert_eq!(
+            fold_positions(&lines).unwrap(),
+            vec![
+                TxPosition {
+                    date: NaiveDate::from_ymd(2020, 3, 1),
+                    beg_pos: 0,
+                    end_pos: 40,
+                },
+                TxPosition {
+                    date: NaiveDate::from_ymd(2020, 3, 2),
+                    beg_pos: 60,
+                    end_pos: 110,
+                },
+            ]
+        );
+        assert!(fold_positions(&["01.03.2020,0,40".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn accounts_file() {
+        let accounts = "account Assets:Cash\n    note Wallet\n; Expenses\n\
+                        Expenses:Food\n\nexpenses:Hygiene\n";
+        assert_eq!(
+            filter_accounts(accounts, ""),
+            vec!["Assets:Cash", "Expenses:Food", "expenses:Hygiene"]
+        );
+        assert_eq!(
+            filter_accounts(accounts, "Exp"),
+            vec!["Expenses:Food", "expenses:Hygiene"]
+        );
+        assert_eq!(filter_accounts(accounts, "wallet"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn invalid_utf8_output() {
+        let out = Output {
+            status: std::process::ExitStatus::default(),
+            stdout: b"Expenses:Caf\xe9\nAssets:Cash\n".to_vec(),
+            stderr: Vec::new(),
+        };
+        assert_eq!(
+            process_ledger_output("accounts", out).unwrap(),
+            vec!["Expenses:Caf\u{fffd}", "Assets:Cash"]
+        );
+    }
+
+    #[test]
+    fn init_file() {
+        let args = |config: &Config| -> Vec<String> {
+            ledger_command("journal.ledger", config)
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect()
+        };
+        let mut config = Config {
+            ledger_args: vec!["--strict".to_owned()],
+            ..Config::default()
+        };
+        assert_eq!(args(&config), vec!["-f", "journal.ledger", "--strict"]);
+        config.ledger_init_file = Some("ledgerrc".to_owned());
+        assert_eq!(
+            args(&config),
+            vec![
+                "--init-file",
+                "ledgerrc",
+                "-f",
+                "journal.ledger",
+                "--strict"
+            ]
+        );
+    }
+
+    #[test]
+    fn init_file_date_format() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("splitter-init-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let journal = "2020/03/01 Lidl\n    Expenses:Food  € 5\n    Assets:Cash\n";
+        fs::write(path("journal.ledger"), journal).unwrap();
+        fs::write(path("ledgerrc"), "--date-format %d.%m.%Y\n").unwrap();
+        // Prints the register row in the init file's date format unless
+        // another one is given on the command line.
+        let script = format!(
+            "#!/bin/sh\n\
+             case \"$*\" in\n\
+             *register*--date-format\\ %Y/%m/%d*) echo 2020/03/01,0,{0} ;;\n\
+             *register*) echo 01.03.2020,0,{0} ;;\n\
+             esac\n",
+            journal.len()
+        );
+        fs::write(path("ledger"), script).unwrap();
+        fs::set_permissions(path("ledger"), fs::Permissions::from_mode(0o755)).unwrap();
+        let config = Config {
+            ledger_binary: path("ledger"),
+            ledger_init_file: Some(path("ledgerrc")),
+            ..Config::default()
+        };
+        let tx = Transaction::new(NaiveDate::from_ymd(2020, 3, 2), "Albert".to_owned());
+        let line = write_transaction(
+            &path("journal.ledger"),
+            &config,
+            &tx.display("%Y/%m/%d"),
+            |_| true,
+        );
+        let written = fs::read_to_string(path("journal.ledger")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(line.unwrap(), 5);
+        assert!(written.starts_with(journal));
+        assert!(written.contains("2020/03/02 Albert"));
+    }
+
+    #[test]
+    fn failing_ledger() {
+        let out = Command::new("sh")
+            .arg("-c")
+            .arg("echo 'Error: Unexpected whitespace at beginning of line' >&2; exit 1")
+            .output()
+            .unwrap();
+        let err = process_ledger_output("accounts", out).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ledger accounts failed (exit status: 1): \
+             Error: Unexpected whitespace at beginning of line"
+        );
+    }
+
+    #[test]
+    fn commodity_matching() {
+        let commodities = || vec!["CZK".to_owned(), "EUR".to_owned(), "€".to_owned()];
+        assert_eq!(
+            matching_commodities(commodities(), "E", CommodityMatching::Prefix),
+            vec!["EUR"]
+        );
+        assert!(matching_commodities(commodities(), "UR", CommodityMatching::Prefix).is_empty());
+        assert_eq!(
+            matching_commodities(commodities(), "UR", CommodityMatching::Substring),
+            vec!["EUR"]
+        );
+        assert_eq!(
+            matching_commodities(commodities(), "k", CommodityMatching::Substring),
+            vec!["CZK"]
+        );
+        assert_eq!(
+            matching_commodities(commodities(), "", CommodityMatching::Substring),
+            commodities()
+        );
+    }
+
+    #[test]
+    fn balance_check() {
+        let mut tx = Transaction::new(NaiveDate::from_ymd(2020, 1, 10), "Lidl".to_owned());
+        tx.add_change("Expenses:Food", Amount("€".to_owned(), dec!(5)));
+        tx.add_change("Expenses:Food", Amount("CZK".to_owned(), dec!(-30)));
+        assert_eq!(
+            check_balanced(&tx).unwrap_err().to_string(),
+            "2020-01-10 Lidl doesn't balance, CZK -30, € 5 remaining"
+        );
+        tx.finalize("Assets:Cash", None);
+        assert!(check_balanced(&tx).is_ok());
+    }
+
+    #[test]
+    fn default_account_directive() {
+        let journal =
+            "; Journal\nbucket Assets:Cash\n\n2020/03/01 Lidl\n    Expenses:Food  EUR 5\n";
+        assert_eq!(
+            find_default_account(journal),
+            Some("Assets:Cash".to_owned())
+        );
+        let journal = "A Assets:Cash\nA Assets:Checking  ; since March\n";
+        assert_eq!(
+            find_default_account(journal),
+            Some("Assets:Checking".to_owned())
+        );
+        let journal = "2020/03/01 Bucket list\n    Expenses:Food  EUR 5\n    Assets:Cash\n";
+        assert_eq!(find_default_account(journal), None);
+    }
+
+    #[test]
+    fn amount_column_detection() {
+        let journal = "\
+2020/03/01 Lidl
+    Expenses:Food                           EUR 5
+    Assets:Cash
+
+2020/03/02 Rent
+    ; Paid by transfer
+    Expenses:Rent                           EUR 500  ; March
+    Assets:Checking                        EUR -500
+
+2020/03/03 Cafe
+\tExpenses:Food                   EUR 3.50
+\tAssets:Cash  ; No amount
+";
+        assert_eq!(detect_amount_column(journal.as_bytes()), Some(44));
+        assert_eq!(posting_amount_column("\tExpenses:Food\t\tEUR 3"), Some(32));
+        assert_eq!(
+            posting_amount_column("    Assets:Checking  -500 EUR"),
+            Some(21)
+        );
+        assert_eq!(posting_amount_column("2020/03/01 Lidl  Market"), None);
+        assert_eq!(detect_amount_column(b"; Empty journal\n"), None);
+    }
+
+    #[test]
+    fn date_format_detection() {
+        let journal = b"; Journal\n\n2020-03-01 Lidl\n    Expenses:Food  EUR 5\n    Assets:Cash\n";
+        assert_eq!(detect_date_format(journal), "%Y-%m-%d");
+        let journal = b"2020/03/01 Lidl\n    Expenses:Food  EUR 5\n    Assets:Cash\n";
+        assert_eq!(detect_date_format(journal), "%Y/%m/%d");
+        assert_eq!(detect_date_format(b"; Empty journal\n"), "%Y/%m/%d");
     }
 }
@@ -1,15 +1,870 @@
+mod commodity;
+mod config;
 mod ledger;
 mod transaction;
 mod tui;
 
+use anyhow::{anyhow, Result};
+use commodity::CommodityFormat;
+use config::{Config, ExpectedSign};
+use ledger::{CommodityMatching, JournalOrder};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
 use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::str::FromStr;
+use transaction::SignDisplay;
 
-fn main() {
-    let ledger_filename = env::args().nth(1);
-    match ledger_filename {
-        Some(filename) => tui::TUIController::new(filename)
-            .run()
-            .expect("Error when running the TUI"),
-        None => println!("Please provide path to your ledger file"),
+/// Configuration file looked for in the working directory.
+const CONFIG_FILE: &str = ".splitterrc";
+
+/// Exit code when splitter couldn't run or ended due to an error.
+const EXIT_ERROR: u8 = 1;
+/// Exit code when a transaction couldn't be written to the ledger file.
+const EXIT_WRITE_FAILED: u8 = 2;
+
+const USAGE: &str = "\
+Usage: splitter [OPTIONS] [LEDGER FILE]
+
+Interactive transaction creator for ledger. When no ledger file is given, the
+one in the LEDGER_FILE environment variable is used.
+
+Options:
+    -f, --file <PATH>               Ledger file to add transactions to
+    -o, --output <PATH>             Write transactions into another file instead
+    --ledger <PATH>                 The ledger executable, default ledger
+    --ledger-arg <OPTION>           Pass an extra option to ledger (repeatable)
+    --init-file <PATH>              Init file ledger reads its options from
+    --date-format <FORMAT>          Format of dates typed into headers
+    --currency <CURRENCY>           Currency used until another one is entered
+    --output-date-format <FORMAT>   Format of dates written to the journal
+    --commodity-format <SAMPLE>     Write a commodity like the sample, e.g. $1,000.00
+    --precision-file <PATH>         Decimal places of commodities, lines like JPY = 0
+    --commodity-matching <MODE>     Complete commodities by prefix (default) or substring
+    --alias <ALIAS=COMMODITY>       Write a commodity typed as ALIAS as COMMODITY
+    --account-alias <ALIAS=ACCOUNT> Enter ACCOUNT by typing ALIAS, e.g. g=Expenses:Food
+    --amount-first                  Enter amounts before accounts: a € 5 Expenses
+    --keyword <KEYWORD=OPERATION>   Also accept KEYWORD for an operation, e.g. +=a
+    --quick                         Start with a transaction dated today, no header
+    --sticky-date                   Reuse the last saved date for headers without one
+    --finalize-account <ACCOUNT>    Account the ff command (Meta-F) finalizes into
+    --accounts-file <PATH>          Complete accounts listed in the file
+    --order <asc|desc>              Order of transactions in the journal
+    --indent <tab|N>                Indent postings with a tab or N spaces
+    --amount-separator <tab|N>      Separate amounts from accounts by a tab or N spaces
+    --amount-column <N>             Start amounts at column N, detected by default
+    --queue                         Write transactions only on save or exit
+    --read-only                     Print finished transactions without writing them
+    --plan-json                     Print where transactions would go as JSON, no writing
+    --group-accounts                Keep all postings of an account together
+    --signs <always|negative-only>  Which amounts are written with a sign
+    --pad-amounts                   Write amounts with all decimal places, e.g. 12.00
+    --debt-prefix <PREFIX>          Accounts names in split map to, default Debts:
+    --tag-counterparties            Tag split transactions with counterparty: <Name>
+    --strict-balance                Refuse to save transactions which don't balance
+    --check-duplicates              Confirm saving transactions already in the journal
+    --check-opposing-changes        Confirm saving accounts changed in both directions
+    --confirm-diff                  Show the journal's diff and confirm writing it
+    --max-amount <[CURRENCY] LIMIT> Confirm adding larger amounts (repeatable)
+    --expect-sign <PREFIX=SIGN>     Warn of changes against positive or negative SIGN
+    --edit-mode <emacs|vi>          Key bindings of the line editor, default emacs
+    --history <PATH>                File keeping entered lines, default history.txt
+    --no-history                    Don't keep entered lines in any history
+    -q, --quiet                     Don't print the balance after every change
+    --verbose                       Print the ledger commands run and insertion offsets
+    --machine-errors                Print errors as ERROR line=N kind=KIND msg=MESSAGE
+    -h, --help                      Print this help
+    -V, --version                   Print the version
+
+Options can also be set in .splitterrc in the working directory or in
+splitter/splitterrc in the XDG configuration directory, one per line like
+`finalize-account = Assets:Cash` or `quiet = true`. Command line options
+override them, repeatable options add to them.
+
+Exits with 0 on success, 1 on errors and 2 if a transaction couldn't be saved.";
+
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    ledger_filename: Option<String>,
+    help: bool,
+    version: bool,
+    config: Config,
+}
+
+fn flag_value<I: Iterator<Item = String>>(flag: &str, args: &mut I) -> Result<String> {
+    args.next()
+        .ok_or_else(|| anyhow!("{} requires a value", flag))
+}
+
+fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Options> {
+    parse_args_onto(Options::default(), args)
+}
+
+/// Parses the arguments on top of the options from the configuration file.
+/// Arguments override the options, except for repeatable ones, which add to
+/// them.
+fn parse_args_onto<I: Iterator<Item = String>>(
+    mut options: Options,
+    mut args: I,
+) -> Result<Options> {
+    let configured_filename = options.ledger_filename.take();
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "-h" | "--help" => options.help = true,
+            "-V" | "--version" => options.version = true,
+            "-f" | "--file" => set_ledger_filename(&mut options, flag_value(&arg, &mut args)?)?,
+            "--ledger-arg" => options
+                .config
+                .ledger_args
+                .push(flag_value(&arg, &mut args)?),
+            "--init-file" => options.config.ledger_init_file = Some(flag_value(&arg, &mut args)?),
+            "--date-format" => options.config.input_date_format = flag_value(&arg, &mut args)?,
+            "--ledger" => options.config.ledger_binary = flag_value(&arg, &mut args)?,
+            "--currency" => options.config.default_currency = Some(flag_value(&arg, &mut args)?),
+            "--history" => options.config.history_file = flag_value(&arg, &mut args)?,
+            "--no-history" => options.config.no_history = true,
+            "--edit-mode" => {
+                options.config.vi_mode = match flag_value(&arg, &mut args)?.as_ref() {
+                    "emacs" => false,
+                    "vi" => true,
+                    mode => {
+                        return Err(anyhow!("Invalid edit mode {}, expecting emacs or vi", mode))
+                    }
+                }
+            }
+            "--amount-first" => options.config.amount_first = true,
+            "--keyword" => {
+                let (keyword, operation) = parse_keyword(&flag_value(&arg, &mut args)?)?;
+                options.config.keywords.insert(keyword, operation);
+            }
+            "--quick" => options.config.quick = true,
+            "--sticky-date" => options.config.sticky_date = true,
+            "--commodity-format" => {
+                let sample = flag_value(&arg, &mut args)?;
+                match CommodityFormat::parse(&sample) {
+                    Some((commodity, format)) => {
+                        options.config.commodity_formats.insert(commodity, format);
+                    }
+                    None => return Err(anyhow!("Invalid commodity format {}", sample)),
+                }
+            }
+            "--precision-file" => {
+                let path = flag_value(&arg, &mut args)?;
+                let contents = fs::read_to_string(&path)
+                    .map_err(|err| anyhow!("Can't read {}: {}", path, err))?;
+                options.config.precisions = parse_precisions(&contents)?;
+            }
+            "--alias" => {
+                let (alias, commodity) = parse_alias(&flag_value(&arg, &mut args)?, "COMMODITY")?;
+                options.config.commodity_aliases.insert(alias, commodity);
+            }
+            "--account-alias" => {
+                let (alias, account) = parse_alias(&flag_value(&arg, &mut args)?, "ACCOUNT")?;
+                options.config.account_aliases.insert(alias, account);
+            }
+            "-q" | "--quiet" => options.config.quiet = true,
+            "--verbose" => options.config.verbose = true,
+            "--machine-errors" => options.config.machine_errors = true,
+            "--check-duplicates" => options.config.check_duplicates = true,
+            "--strict-balance" => options.config.strict_balance = true,
+            "--check-opposing-changes" => options.config.check_opposing_changes = true,
+            "--confirm-diff" => options.config.confirm_diff = true,
+            "--group-accounts" => options.config.group_accounts = true,
+            "--queue" => options.config.queue_transactions = true,
+            "--read-only" => options.config.read_only = true,
+            "--plan-json" => options.config.plan_json = true,
+            "--pad-amounts" => options.config.pad_amounts = true,
+            "--indent" => {
+                options.config.posting_indent = parse_whitespace(&flag_value(&arg, &mut args)?)?
+            }
+            "--amount-separator" => {
+                options.config.amount_separator = parse_whitespace(&flag_value(&arg, &mut args)?)?
+            }
+            "--tag-counterparties" => options.config.tag_counterparties = true,
+            "--expect-sign" => {
+                let value = flag_value(&arg, &mut args)?;
+                let invalid = || {
+                    anyhow!(
+                        "Invalid sign expectation {}, expecting PREFIX=positive or PREFIX=negative",
+                        value
+                    )
+                };
+                let (prefix, sign) = value.split_once('=').ok_or_else(invalid)?;
+                let sign = match sign.trim() {
+                    "positive" => ExpectedSign::Positive,
+                    "negative" => ExpectedSign::Negative,
+                    _ => return Err(invalid()),
+                };
+                if prefix.trim().is_empty() {
+                    return Err(invalid());
+                }
+                options
+                    .config
+                    .expected_signs
+                    .push((prefix.trim().to_owned(), sign));
+            }
+            "--max-amount" => {
+                let value = flag_value(&arg, &mut args)?;
+                match parse_max_amount(&value)? {
+                    (Some(currency), limit) => {
+                        options.config.currency_max_amounts.insert(currency, limit);
+                    }
+                    (None, limit) => options.config.max_amount = Some(limit),
+                }
+            }
+            "--amount-column" => {
+                let column = flag_value(&arg, &mut args)?;
+                options.config.amount_column = Some(
+                    column
+                        .parse()
+                        .map_err(|_| anyhow!("Invalid amount column {}", column))?,
+                );
+            }
+            "--signs" => {
+                options.config.sign_display = match flag_value(&arg, &mut args)?.as_ref() {
+                    "always" => SignDisplay::Always,
+                    "negative-only" => SignDisplay::NegativeOnly,
+                    signs => {
+                        return Err(anyhow!(
+                            "Invalid signs {}, expecting always or negative-only",
+                            signs
+                        ))
+                    }
+                }
+            }
+            "--commodity-matching" => {
+                options.config.commodity_matching = match flag_value(&arg, &mut args)?.as_ref() {
+                    "prefix" => CommodityMatching::Prefix,
+                    "substring" => CommodityMatching::Substring,
+                    matching => {
+                        return Err(anyhow!(
+                            "Invalid commodity matching {}, expecting prefix or substring",
+                            matching
+                        ))
+                    }
+                }
+            }
+            "--order" => {
+                options.config.order = match flag_value(&arg, &mut args)?.as_ref() {
+                    "asc" => Some(JournalOrder::Ascending),
+                    "desc" => Some(JournalOrder::Descending),
+                    order => return Err(anyhow!("Invalid order {}, expecting asc or desc", order)),
+                }
+            }
+            "-o" | "--output" => options.config.output_file = Some(flag_value(&arg, &mut args)?),
+            "--accounts-file" => options.config.accounts_file = Some(flag_value(&arg, &mut args)?),
+            "--debt-prefix" => {
+                let mut prefix = flag_value(&arg, &mut args)?;
+                if !prefix.ends_with(':') {
+                    prefix.push(':');
+                }
+                options.config.debt_prefix = prefix;
+            }
+            "--finalize-account" => {
+                options.config.finalize_account = Some(flag_value(&arg, &mut args)?)
+            }
+            "--output-date-format" => {
+                options.config.output_date_format = Some(flag_value(&arg, &mut args)?)
+            }
+            _ if arg.starts_with('-') && arg != "-" => {
+                return Err(anyhow!("Unknown option {}", arg))
+            }
+            _ => set_ledger_filename(&mut options, arg)?,
+        }
+    }
+    options.ledger_filename = options.ledger_filename.or(configured_filename);
+    Ok(options)
+}
+
+/// Turns lines of the configuration file, like `finalize-account =
+/// Assets:Cash` or `quiet = true`, into the options they stand for. Empty
+/// lines and comments starting with `#` are skipped.
+fn config_file_args(contents: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Invalid line {}, expecting OPTION = VALUE", line))?;
+        let flag = format!("--{}", key.trim());
+        match value.trim() {
+            "true" => args.push(flag),
+            "false" => {}
+            value => {
+                args.push(flag);
+                args.push(value.to_owned());
+            }
+        }
+    }
+    Ok(args)
+}
+
+/// Looks for the configuration file in the working directory, then in the
+/// XDG configuration directory.
+fn config_file_path() -> Option<PathBuf> {
+    let local = PathBuf::from(CONFIG_FILE);
+    if local.is_file() {
+        return Some(local);
+    }
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("splitter").join("splitterrc")).filter(|path| path.is_file())
+}
+
+/// Reads the options set in the configuration file, if there is one.
+fn config_file_options() -> Result<Options> {
+    let path = match config_file_path() {
+        Some(path) => path,
+        None => return Ok(Options::default()),
     };
+    let contents = fs::read_to_string(&path)
+        .map_err(|err| anyhow!("Can't read {}: {}", path.display(), err))?;
+    config_file_args(&contents)
+        .and_then(|args| parse_args(args.into_iter()))
+        .map_err(|err| anyhow!("{} in {}", err, path.display()))
+}
+
+/// Parses `tab` or a number of spaces into the whitespace itself.
+fn parse_whitespace(value: &str) -> Result<String> {
+    match value {
+        "tab" => Ok("\t".to_owned()),
+        spaces => match spaces.parse::<usize>() {
+            Ok(count) => Ok(" ".repeat(count)),
+            Err(_) => Err(anyhow!(
+                "Invalid whitespace {}, expecting tab or a number of spaces",
+                value
+            )),
+        },
+    }
+}
+
+/// Parses a limit like `500`, `€500` or `500 CZK`, returning its currency if
+/// it has one.
+fn parse_max_amount(value: &str) -> Result<(Option<String>, Decimal)> {
+    let invalid = || anyhow!("Invalid maximum amount {}", value);
+    let first_digit = value
+        .find(|c: char| c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let last_digit = value
+        .rfind(|c: char| c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let limit = Decimal::from_str(&value[first_digit..=last_digit]).map_err(|_| invalid())?;
+    let currency = match (value[..first_digit].trim(), value[last_digit + 1..].trim()) {
+        ("", "") => None,
+        (currency, "") | ("", currency) => Some(currency.to_owned()),
+        _ => return Err(invalid()),
+    };
+    Ok((currency, limit))
+}
+
+/// Parses lines like `JPY = 0`, giving the decimal places of commodities.
+/// Empty lines and comments starting with `#` or `;` are skipped.
+fn parse_precisions(contents: &str) -> Result<HashMap<String, u32>> {
+    let mut precisions = HashMap::new();
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        let invalid = || anyhow!("Invalid precision {}, expecting COMMODITY = PLACES", line);
+        let (commodity, places) = line.split_once('=').ok_or_else(invalid)?;
+        let places = places.trim().parse().map_err(|_| invalid())?;
+        if commodity.trim().is_empty() {
+            return Err(invalid());
+        }
+        precisions.insert(commodity.trim().to_owned(), places);
+    }
+    Ok(precisions)
+}
+
+/// Parses a custom operation keyword like `+=a`, returning the keyword and
+/// the operation.
+fn parse_keyword(value: &str) -> Result<(String, String)> {
+    match value.split_once('=') {
+        Some((keyword, operation))
+            if !keyword.is_empty() && !keyword.contains(char::is_whitespace) =>
+        {
+            if !tui::is_operation(operation) {
+                return Err(anyhow!("Unknown operation {} in {}", operation, value));
+            }
+            Ok((keyword.to_owned(), operation.to_owned()))
+        }
+        _ => Err(anyhow!(
+            "Invalid keyword {}, expecting KEYWORD=OPERATION",
+            value
+        )),
+    }
+}
+
+/// Parses an alias like `EUR=€`, returning the alias and its target, which
+/// `name` describes in the error message.
+fn parse_alias(value: &str, name: &str) -> Result<(String, String)> {
+    match value.split_once('=') {
+        Some((alias, target)) if !alias.trim().is_empty() && !target.trim().is_empty() => {
+            Ok((alias.trim().to_owned(), target.trim().to_owned()))
+        }
+        _ => Err(anyhow!("Invalid alias {}, expecting ALIAS={}", value, name)),
+    }
+}
+
+fn set_ledger_filename(options: &mut Options, filename: String) -> Result<()> {
+    if options.ledger_filename.is_some() {
+        return Err(anyhow!("Unexpected argument {}", filename));
+    }
+    options.ledger_filename = Some(filename);
+    Ok(())
+}
+
+/// The ledger file given in arguments takes precedence over `LEDGER_FILE`.
+fn ledger_filename(argument: Option<String>, env_value: Option<String>) -> Option<String> {
+    argument.or_else(|| env_value.filter(|filename| !filename.is_empty()))
+}
+
+fn main() -> ExitCode {
+    let configured = match config_file_options() {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::from(EXIT_ERROR);
+        }
+    };
+    let options = match parse_args_onto(configured, env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            return ExitCode::from(EXIT_ERROR);
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    if options.version {
+        println!("splitter {}", env!("CARGO_PKG_VERSION"));
+        return ExitCode::SUCCESS;
+    }
+    let filename = match ledger_filename(options.ledger_filename, env::var("LEDGER_FILE").ok()) {
+        Some(filename) => filename,
+        None => {
+            eprintln!("Please provide path to your ledger file or set LEDGER_FILE");
+            return ExitCode::from(EXIT_ERROR);
+        }
+    };
+    let mut controller = tui::TUIController::new(filename, options.config);
+    if let Err(err) = controller.run() {
+        eprintln!("Error when running the TUI: {}", err);
+        return ExitCode::from(EXIT_ERROR);
+    }
+    if controller.write_failed() {
+        return ExitCode::from(EXIT_WRITE_FAILED);
+    }
+    ExitCode::SUCCESS
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[allow(dead_code)]
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter()
+            .map(|a| a.to_string())
+            .collect::<Vec<String>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn ledger_args() {
+        let options = parse_args(args(&[
+            "--ledger-arg",
+            "--strict",
+            "journal.ledger",
+            "--ledger-arg",
+            "--price-db=prices.db",
+        ]))
+        .unwrap();
+        assert_eq!(options.ledger_filename, Some("journal.ledger".to_owned()));
+        assert_eq!(
+            options.config.ledger_args,
+            vec!["--strict", "--price-db=prices.db"]
+        );
+        assert!(parse_args(args(&["journal.ledger", "--ledger-arg"])).is_err());
+        let options = parse_args(args(&["--init-file", "ledgerrc", "journal.ledger"])).unwrap();
+        assert_eq!(options.config.ledger_init_file, Some("ledgerrc".to_owned()));
+        assert!(parse_args(args(&["journal.ledger", "other.ledger"])).is_err());
+    }
+
+    #[test]
+    fn help_and_version() {
+        let options = parse_args(args(&["--help"])).unwrap();
+        assert!(options.help);
+        assert_eq!(options.ledger_filename, None);
+        assert!(parse_args(args(&["-V"])).unwrap().version);
+        assert!(parse_args(args(&["--loud", "journal.ledger"])).is_err());
+    }
+
+    #[test]
+    fn ledger_file_flag() {
+        let options = parse_args(args(&["--file", "journal.ledger"])).unwrap();
+        assert_eq!(options.ledger_filename, Some("journal.ledger".to_owned()));
+        assert!(parse_args(args(&["-f", "journal.ledger", "other.ledger"])).is_err());
+        assert!(parse_args(args(&["-f"])).is_err());
+    }
+
+    #[test]
+    fn ledger_file_env() {
+        let from_env = Some("env.ledger".to_owned());
+        assert_eq!(
+            ledger_filename(Some("journal.ledger".to_owned()), from_env.clone()),
+            Some("journal.ledger".to_owned())
+        );
+        assert_eq!(ledger_filename(None, from_env.clone()), from_env);
+        assert_eq!(ledger_filename(None, Some("".to_owned())), None);
+        assert_eq!(ledger_filename(None, None), None);
+    }
+
+    #[test]
+    fn date_formats() {
+        let options = parse_args(args(&[
+            "--date-format",
+            "%d.%m.%Y",
+            "--output-date-format",
+            "%Y/%m/%d",
+            "journal.ledger",
+        ]))
+        .unwrap();
+        assert_eq!(options.config.input_date_format, "%d.%m.%Y");
+        assert_eq!(
+            options.config.output_date_format,
+            Some("%Y/%m/%d".to_owned())
+        );
+        let options = parse_args(args(&["journal.ledger"])).unwrap();
+        assert_eq!(options.config, Config::default());
+    }
+
+    #[test]
+    fn amount_first() {
+        let options = parse_args(args(&["--amount-first", "journal.ledger"])).unwrap();
+        assert!(options.config.amount_first);
+    }
+
+    #[test]
+    fn commodity_formats() {
+        let options = parse_args(args(&[
+            "--commodity-format",
+            "$1,000.00",
+            "--commodity-format",
+            "1.000,00 €",
+            "journal.ledger",
+        ]))
+        .unwrap();
+        assert_eq!(options.config.commodity_formats.len(), 2);
+        assert!(options.config.commodity_formats["€"].suffix);
+        assert!(!options.config.commodity_formats["$"].suffix);
+        assert!(parse_args(args(&["--commodity-format", "1000", "journal.ledger"])).is_err());
+    }
+
+    #[test]
+    fn precisions() {
+        let precisions = parse_precisions("# Decimal places\nJPY = 0\n\nBHD=3\n").unwrap();
+        let config = Config {
+            precisions,
+            ..Config::default()
+        };
+        assert_eq!(config.precision("JPY"), 0);
+        assert_eq!(config.precision("BHD"), 3);
+        assert_eq!(config.precision("€"), 2);
+        assert!(parse_precisions("JPY 0").is_err());
+        assert!(parse_precisions("JPY = -1").is_err());
+        assert!(parse_precisions(" = 1").is_err());
+        assert!(parse_args(args(&["--precision-file", "missing.ini", "journal.ledger"])).is_err());
+    }
+
+    #[test]
+    fn signs() {
+        assert_eq!(
+            parse_args(args(&["journal.ledger"]))
+                .unwrap()
+                .config
+                .sign_display,
+            SignDisplay::NegativeOnly
+        );
+        let options = parse_args(args(&["--signs", "always", "journal.ledger"])).unwrap();
+        assert_eq!(options.config.sign_display, SignDisplay::Always);
+        assert!(parse_args(args(&["--signs", "positive", "journal.ledger"])).is_err());
+    }
+
+    #[test]
+    fn commodity_matching() {
+        assert_eq!(
+            parse_args(args(&["journal.ledger"]))
+                .unwrap()
+                .config
+                .commodity_matching,
+            CommodityMatching::Prefix
+        );
+        let options = parse_args(args(&[
+            "--commodity-matching",
+            "substring",
+            "journal.ledger",
+        ]))
+        .unwrap();
+        assert_eq!(
+            options.config.commodity_matching,
+            CommodityMatching::Substring
+        );
+        assert!(parse_args(args(&["--commodity-matching", "fuzzy", "journal.ledger"])).is_err());
+    }
+
+    #[test]
+    fn config_file() {
+        let contents = "\
+# Defaults
+ledger = /usr/local/bin/ledger
+finalize-account = Assets:Checking Account
+currency = €
+edit-mode = vi
+quiet = true
+amount-first = false
+ledger-arg = --strict
+file = main.ledger
+";
+        let configured = parse_args(config_file_args(contents).unwrap().into_iter()).unwrap();
+        assert_eq!(configured.config.ledger_binary, "/usr/local/bin/ledger");
+        assert_eq!(
+            configured.config.finalize_account,
+            Some("Assets:Checking Account".to_owned())
+        );
+        assert_eq!(configured.config.default_currency, Some("€".to_owned()));
+        assert!(configured.config.vi_mode);
+        assert!(configured.config.quiet);
+        assert!(!configured.config.amount_first);
+
+        let options = parse_args_onto(
+            configured,
+            args(&[
+                "--finalize-account",
+                "Assets:Cash",
+                "--ledger-arg",
+                "--pedantic",
+                "journal.ledger",
+            ]),
+        )
+        .unwrap();
+        assert_eq!(options.ledger_filename, Some("journal.ledger".to_owned()));
+        assert_eq!(
+            options.config.finalize_account,
+            Some("Assets:Cash".to_owned())
+        );
+        assert_eq!(options.config.ledger_args, vec!["--strict", "--pedantic"]);
+        assert_eq!(options.config.default_currency, Some("€".to_owned()));
+
+        let configured = parse_args(config_file_args(contents).unwrap().into_iter()).unwrap();
+        let options = parse_args_onto(configured, args(&[])).unwrap();
+        assert_eq!(options.ledger_filename, Some("main.ledger".to_owned()));
+
+        assert!(config_file_args("quiet").is_err());
+        assert!(parse_args(config_file_args("colour = red").unwrap().into_iter()).is_err());
+        assert!(parse_args(args(&["--edit-mode", "nano", "journal.ledger"])).is_err());
+    }
+
+    #[test]
+    fn keywords() {
+        let options = parse_args(args(&[
+            "--keyword",
+            "+=a",
+            "--keyword",
+            "x=s",
+            "journal.ledger",
+        ]))
+        .unwrap();
+        assert_eq!(options.config.keywords["+"], "a");
+        assert_eq!(options.config.keywords["x"], "s");
+        assert!(parse_args(args(&["--keyword", "+=add", "journal.ledger"])).is_err());
+        assert!(parse_args(args(&["--keyword", "=a", "journal.ledger"])).is_err());
+        assert!(parse_args(args(&["--keyword", "+", "journal.ledger"])).is_err());
+    }
+
+    #[test]
+    fn aliases() {
+        let options = parse_args(args(&[
+            "--alias",
+            "EUR=€",
+            "--alias",
+            "Kč = CZK",
+            "journal.ledger",
+        ]))
+        .unwrap();
+        assert_eq!(options.config.commodity_aliases["EUR"], "€");
+        assert_eq!(options.config.commodity_aliases["Kč"], "CZK");
+        assert!(parse_args(args(&["--alias", "EUR", "journal.ledger"])).is_err());
+        assert!(parse_args(args(&["--alias", "=€", "journal.ledger"])).is_err());
+
+        let options = parse_args(args(&[
+            "--account-alias",
+            "g = Expenses:Food:Groceries",
+            "journal.ledger",
+        ]))
+        .unwrap();
+        assert_eq!(
+            options.config.account_aliases["g"],
+            "Expenses:Food:Groceries"
+        );
+        assert!(parse_args(args(&["--account-alias", "g=", "journal.ledger"])).is_err());
+    }
+
+    #[test]
+    fn expected_signs() {
+        let options = parse_args(args(&[
+            "--expect-sign",
+            "Expenses:=positive",
+            "--expect-sign",
+            "Expenses:Refunds = negative",
+            "journal.ledger",
+        ]))
+        .unwrap();
+        let config = options.config;
+        assert_eq!(
+            config.expected_sign("Expenses:Food"),
+            Some(ExpectedSign::Positive)
+        );
+        assert_eq!(
+            config.expected_sign("Expenses:Refunds:Shoes"),
+            Some(ExpectedSign::Negative)
+        );
+        assert_eq!(config.expected_sign("Assets:Cash"), None);
+        assert!(parse_args(args(&["--expect-sign", "Expenses:", "journal.ledger"])).is_err());
+        assert!(parse_args(args(&["--expect-sign", "Expenses:=up", "journal.ledger"])).is_err());
+    }
+
+    #[test]
+    fn max_amounts() {
+        let options = parse_args(args(&[
+            "--max-amount",
+            "500",
+            "--max-amount",
+            "€1000",
+            "--max-amount",
+            "20000 CZK",
+            "journal.ledger",
+        ]))
+        .unwrap();
+        assert_eq!(options.config.max_amount("$"), Some(Decimal::new(500, 0)));
+        assert_eq!(options.config.max_amount("€"), Some(Decimal::new(1000, 0)));
+        assert_eq!(
+            options.config.max_amount("CZK"),
+            Some(Decimal::new(20000, 0))
+        );
+        assert_eq!(Config::default().max_amount("€"), None);
+        assert!(parse_args(args(&["--max-amount", "€", "journal.ledger"])).is_err());
+        assert!(parse_args(args(&["--max-amount", "€ 5 $", "journal.ledger"])).is_err());
+    }
+
+    #[test]
+    fn finalize_account() {
+        let options = parse_args(args(&[
+            "--finalize-account",
+            "Assets:Checking",
+            "journal.ledger",
+        ]))
+        .unwrap();
+        assert_eq!(
+            options.config.finalize_account,
+            Some("Assets:Checking".to_owned())
+        );
+    }
+
+    #[test]
+    fn output_file() {
+        let options = parse_args(args(&["journal.ledger", "-o", "inbox.ledger"])).unwrap();
+        assert_eq!(options.ledger_filename, Some("journal.ledger".to_owned()));
+        assert_eq!(options.config.output_file, Some("inbox.ledger".to_owned()));
+        assert!(parse_args(args(&["journal.ledger", "--output"])).is_err());
+    }
+
+    #[test]
+    fn accounts_file() {
+        let options = parse_args(args(&[
+            "--accounts-file",
+            "accounts.ledger",
+            "journal.ledger",
+        ]))
+        .unwrap();
+        assert_eq!(
+            options.config.accounts_file,
+            Some("accounts.ledger".to_owned())
+        );
+        assert!(parse_args(args(&["journal.ledger", "--accounts-file"])).is_err());
+    }
+
+    #[test]
+    fn indentation() {
+        let options = parse_args(args(&[
+            "--indent",
+            "4",
+            "--amount-separator",
+            "tab",
+            "journal.ledger",
+        ]))
+        .unwrap();
+        assert_eq!(options.config.posting_indent, "    ");
+        assert_eq!(options.config.amount_separator, "\t");
+        let options = parse_args(args(&["journal.ledger"])).unwrap();
+        assert_eq!(options.config.posting_indent, "\t");
+        assert_eq!(options.config.amount_separator, "  ");
+        assert!(parse_args(args(&["--indent", "spaces", "journal.ledger"])).is_err());
+        let options = parse_args(args(&["--amount-column", "48", "journal.ledger"])).unwrap();
+        assert_eq!(options.config.amount_column, Some(48));
+        assert!(parse_args(args(&["--amount-column", "-1", "journal.ledger"])).is_err());
+    }
+
+    #[test]
+    fn debt_prefix() {
+        let options = parse_args(args(&[
+            "--debt-prefix",
+            "Liabilities:Owed",
+            "journal.ledger",
+        ]))
+        .unwrap();
+        assert_eq!(options.config.debt_prefix, "Liabilities:Owed:");
+        let options = parse_args(args(&["journal.ledger"])).unwrap();
+        assert_eq!(options.config.debt_prefix, "Debts:");
+    }
+
+    #[test]
+    fn order() {
+        let options = parse_args(args(&["--order", "desc", "journal.ledger"])).unwrap();
+        assert_eq!(options.config.order, Some(JournalOrder::Descending));
+        let options = parse_args(args(&["--order", "asc", "journal.ledger"])).unwrap();
+        assert_eq!(options.config.order, Some(JournalOrder::Ascending));
+        assert!(parse_args(args(&["--order", "newest", "journal.ledger"])).is_err());
+    }
+
+    #[test]
+    fn quiet() {
+        assert!(
+            parse_args(args(&["-q", "journal.ledger"]))
+                .unwrap()
+                .config
+                .quiet
+        );
+        assert!(!parse_args(args(&["journal.ledger"])).unwrap().config.quiet);
+    }
+
+    #[test]
+    fn verbose() {
+        let options = parse_args(args(&["--verbose", "journal.ledger"])).unwrap();
+        assert!(options.config.verbose);
+        assert!(
+            !parse_args(args(&["journal.ledger"]))
+                .unwrap()
+                .config
+                .verbose
+        );
+    }
 }
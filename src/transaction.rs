@@ -1,18 +1,50 @@
+use crate::commodity::{CommodityFormat, CommodityFormats};
 use chrono::NaiveDate;
 use rust_decimal::Decimal;
 use rust_decimal_macros::*;
 use std::collections::HashMap;
 use std::fmt;
 use std::iter::once;
+use std::str::FromStr;
+
+/// Default prefix of the accounts of people sharing expenses.
+pub const DEBTS_PREFIX: &str = "Debts:";
+
+/// Columns between tab stops when measuring how wide the journal's lines are.
+const TAB_WIDTH: usize = 8;
 
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Amount(pub String, pub Decimal);
 
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.0, self.1)
+    }
+}
+
+type Posting<'a> = (&'a str, &'a Amount);
+
+/// Which amounts are written with a sign.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignDisplay {
+    /// Only negative amounts have a sign, like ledger writes them.
+    NegativeOnly,
+    /// Positive amounts get a `+` sign as well.
+    Always,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Transaction {
     pub date: NaiveDate,
     pub description: String,
     pub changes: HashMap<String, Vec<Amount>>,
+    /// Metadata tags, written as `; key: value` comments.
+    pub tags: Vec<(String, String)>,
+    /// Balances the accounts are asserted to have after the transaction.
+    pub assertions: Vec<(String, Amount)>,
+    /// Amounts added together with their total cost in another commodity,
+    /// written as `CZK 250 @@ € 10`.
+    pub conversions: Vec<(String, Amount, Amount)>,
 }
 
 impl Transaction {
@@ -21,38 +53,130 @@ impl Transaction {
             date,
             description,
             changes: HashMap::new(),
+            tags: Vec::new(),
+            assertions: Vec::new(),
+            conversions: Vec::new(),
         }
     }
 
+    pub fn add_tag(&mut self, key: &str, value: &str) {
+        self.tags.push((key.to_owned(), value.to_owned()));
+    }
+
+    /// Asserts the balance of the account in the amount's currency, replacing
+    /// any previous assertion of it.
+    pub fn add_assertion(&mut self, account: &str, amount: Amount) {
+        self.assertions
+            .retain(|(a, asserted)| a != account || asserted.0 != amount.0);
+        self.assertions.push((account.to_owned(), amount));
+    }
+
+    /// Adds the tag unless the transaction already has it.
+    pub fn add_unique_tag(&mut self, key: &str, value: &str) {
+        if !self.tags.iter().any(|(k, v)| k == key && v == value) {
+            self.add_tag(key, value);
+        }
+    }
+
+    /// Adds the amount to the account. Changes cancelling each other out
+    /// don't leave a zero posting behind.
     pub fn add_change(&mut self, account: &str, amount: Amount) {
-        self.changes
-            .entry(account.to_owned())
-            .and_modify(|v| {
-                if let Some(pos) = v.iter().position(|am| am.0 == amount.0) {
-                    v[pos].1 += amount.1;
-                } else {
-                    v.push(amount.clone());
-                    v.sort();
-                }
-            })
-            .or_insert(vec![amount]);
+        let amounts = self.changes.entry(account.to_owned()).or_default();
+        match amounts.iter().position(|am| am.0 == amount.0) {
+            Some(pos) => amounts[pos].1 += amount.1,
+            None => {
+                amounts.push(amount);
+                amounts.sort();
+            }
+        }
+        amounts.retain(|am| am.1 != dec!(0));
+        if amounts.is_empty() {
+            self.changes.remove(account);
+        }
+    }
+
+    /// Adds the amount to the account at the given total cost. The
+    /// transaction is balanced by the cost, not by the amount itself.
+    pub fn add_converted_change(&mut self, account: &str, amount: Amount, cost: Amount) {
+        self.conversions.push((account.to_owned(), amount, cost));
     }
 
-    pub fn add_split_change(&mut self, account: &str, split_account: &str, amount: Amount) {
-        let half = Amount(amount.0, amount.1 / dec!(2));
-        self.add_change(account, half.clone());
-        self.add_change(split_account, half);
+    /// Splits the amount in half between the accounts. The account gets half
+    /// of the amount rounded to its precision (but at least to the given
+    /// number of decimal places), the split account the rest, so that the
+    /// halves always sum up to the amount.
+    pub fn add_split_change(
+        &mut self,
+        account: &str,
+        split_account: &str,
+        amount: Amount,
+        precision: u32,
+    ) {
+        let precision = amount.1.scale().max(precision);
+        let half = (amount.1 / dec!(2)).round_dp(precision);
+        self.add_change(account, Amount(amount.0.clone(), half));
+        self.add_change(split_account, Amount(amount.0, amount.1 - half));
+    }
+
+    /// Moves the amount from one account to another, keeping the transaction
+    /// balanced.
+    pub fn add_transfer(&mut self, from: &str, to: &str, amount: Amount) {
+        self.add_change(from, Amount(amount.0.clone(), -amount.1));
+        self.add_change(to, amount);
+    }
+
+    /// Splits the amount evenly between the account and a debt account for
+    /// each of the named people, under the debt prefix.
+    pub fn add_named_split_change(
+        &mut self,
+        account: &str,
+        names: &[&str],
+        amount: Amount,
+        debt_prefix: &str,
+        precision: u32,
+    ) {
+        let mut weights = vec![(account.to_owned(), dec!(1))];
+        weights.extend(
+            names
+                .iter()
+                .map(|name| (debt_account(debt_prefix, name), dec!(1))),
+        );
+        self.add_weighted_change(&weights, amount, precision);
+    }
+
+    /// Distributes the amount between the accounts in proportion to their
+    /// weights. Shares are rounded to the amount's precision (but at least
+    /// to the given number of decimal places), the rounding remainder goes to
+    /// the first account so that the shares always sum up to the amount. The
+    /// weights must not sum to zero.
+    pub fn add_weighted_change(
+        &mut self,
+        weights: &[(String, Decimal)],
+        amount: Amount,
+        precision: u32,
+    ) {
+        let total_weight: Decimal = weights.iter().map(|(_, weight)| *weight).sum();
+        let precision = amount.1.scale().max(precision);
+        let shares: Vec<Decimal> = weights
+            .iter()
+            .map(|(_, weight)| (amount.1 * *weight / total_weight).round_dp(precision))
+            .collect();
+        let remainder = amount.1 - shares.iter().cloned().sum::<Decimal>();
+        for (i, ((account, _), share)) in weights.iter().zip(shares).enumerate() {
+            let share = if i == 0 { share + remainder } else { share };
+            self.add_change(account, Amount(amount.0.clone(), share));
+        }
     }
 
     pub fn balance(&self) -> Vec<Amount> {
         let mut balances = HashMap::new();
-        for amounts in self.changes.values() {
-            for amount in amounts {
-                balances
-                    .entry(&amount.0)
-                    .and_modify(|a: &mut Decimal| *a = amount.1 + *a)
-                    .or_insert(amount.1);
-            }
+        // Converted amounts are balanced by their cost.
+        let costs = self.conversions.iter().map(|(_, _, cost)| cost);
+        for amount in self.changes.values().flatten().chain(costs) {
+            balances
+                .entry(&amount.0)
+                .and_modify(|a: &mut Decimal| *a = amount.1 + *a)
+                .or_insert(amount.1);
         }
         let mut balance_vec: Vec<Amount> = balances
             .iter()
@@ -62,52 +186,529 @@ impl Transaction {
         balance_vec
     }
 
-    pub fn finalize(&mut self, account: &str) {
-        for amount in self.balance() {
-            self.add_change(account, Amount(amount.0, -amount.1));
+    /// Checks whether the transactions have the same date, description and
+    /// postings, ignoring differences in formatting like trailing zeros.
+    pub fn is_duplicate_of(&self, other: &Transaction) -> bool {
+        self.date == other.date
+            && self.description.trim() == other.description.trim()
+            && self.normalized_postings() == other.normalized_postings()
+    }
+
+    fn normalized_postings(&self) -> Vec<(&str, String, Decimal)> {
+        let mut postings: Vec<(&str, String, Decimal)> = self
+            .converted_amounts()
+            .into_iter()
+            .filter(|(_, amount)| amount.1 != dec!(0))
+            .map(|(account, amount)| (account, amount.0.clone(), amount.1.normalize()))
+            .collect();
+        postings.sort();
+        postings
+    }
+
+    /// Returns the accounts and currencies whose amount was changed in the
+    /// opposite direction since the earlier state of the transaction, like
+    /// an expense which was reduced again.
+    pub fn opposing_changes(&self, earlier: &Transaction) -> Vec<(String, String)> {
+        let mut opposing = Vec::new();
+        for (account, amount) in earlier.amounts() {
+            let current = self
+                .changes
+                .get(account)
+                .and_then(|amounts| amounts.iter().find(|a| a.0 == amount.0))
+                .map_or(dec!(0), |a| a.1);
+            let change = current - amount.1;
+            if change != dec!(0) && change.is_sign_negative() != amount.1.is_sign_negative() {
+                opposing.push((account.to_owned(), amount.0.clone()));
+            }
+        }
+        opposing.sort();
+        opposing
+    }
+
+    /// Returns how much the amounts of accounts changed since the earlier
+    /// state of the transaction, leaving out the unchanged ones. Converted
+    /// amounts count in their own commodity, not in their cost.
+    pub fn changes_since(&self, earlier: &Transaction) -> Vec<(String, Amount)> {
+        let amount_of = |tx: &Transaction, account: &str, currency: &str| {
+            tx.converted_amounts()
+                .into_iter()
+                .filter(|(acc, amount)| *acc == account && amount.0 == currency)
+                .map(|(_, amount)| amount.1)
+                .sum::<Decimal>()
+        };
+        let mut changes: Vec<(String, Amount)> = Vec::new();
+        let postings = self.converted_amounts().into_iter();
+        for (account, amount) in postings.chain(earlier.converted_amounts()) {
+            let change =
+                amount_of(self, account, &amount.0) - amount_of(earlier, account, &amount.0);
+            let changed = (account.to_owned(), Amount(amount.0.clone(), change));
+            if change != dec!(0) && !changes.contains(&changed) {
+                changes.push(changed);
+            }
+        }
+        changes.sort();
+        changes
+    }
+
+    /// Returns the balance of currencies which don't balance out to zero.
+    pub fn unbalanced(&self) -> Vec<Amount> {
+        self.balance()
+            .into_iter()
+            .filter(|amount| amount.1 != dec!(0))
+            .collect()
+    }
+
+    /// Checks whether the transaction (or just the currency, when given)
+    /// balances out to zero.
+    pub fn is_balanced(&self, currency: Option<&str>) -> bool {
+        self.unbalanced()
+            .iter()
+            .all(|amount| currency.is_some_and(|c| c != amount.0))
+    }
+
+    /// Balances the transaction using the account. When a currency is given,
+    /// only that currency gets balanced. Currencies which are balanced
+    /// already are left alone, so finalizing twice changes nothing.
+    pub fn finalize(&mut self, account: &str, currency: Option<&str>) {
+        for amount in self.unbalanced() {
+            if currency.is_none_or(|c| c == amount.0) {
+                self.add_change(account, Amount(amount.0, -amount.1));
+            }
         }
     }
 
-    fn amounts(&self) -> Vec<(&str, &Amount)> {
+    fn amounts(&self) -> Vec<Posting<'_>> {
         let mut amount_vec = Vec::new();
         for (account, amounts) in &self.changes {
-            for amount in amounts {
+            for amount in amounts.iter().filter(|amount| amount.1 != dec!(0)) {
                 amount_vec.push((account.as_ref(), amount));
             }
         }
         amount_vec
     }
+
+    /// Returns the amounts of the postings, including the converted ones in
+    /// their own commodity.
+    fn converted_amounts(&self) -> Vec<Posting<'_>> {
+        let converted = self
+            .conversions
+            .iter()
+            .map(|(account, amount, _)| (account.as_str(), amount));
+        self.amounts().into_iter().chain(converted).collect()
+    }
+}
+
+/// Maps a bare name, like `anna`, to a debt account under the prefix, like
+/// `Debts:Anna`.
+pub fn debt_account(debt_prefix: &str, name: &str) -> String {
+    let mut chars = name.chars();
+    let mut account = debt_prefix.to_owned();
+    if let Some(first) = chars.next() {
+        account.extend(first.to_uppercase());
+        account.push_str(chars.as_str());
+    }
+    account
+}
+
+/// Returns the person owing the debt account under the prefix, like `Pete`
+/// for `Debts:Pete`, or `None` for other accounts.
+pub fn counterparty<'a>(debt_prefix: &str, account: &'a str) -> Option<&'a str> {
+    if account.starts_with(debt_prefix) {
+        account.rsplit(':').next().filter(|name| !name.is_empty())
+    } else {
+        None
+    }
+}
+
+/// Parses a transaction written by the default `Display` implementation.
+impl FromStr for Transaction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Transaction, String> {
+        let mut lines = s.lines();
+        let header = lines.next().ok_or("Missing transaction header")?;
+        let mut header_fields = header.splitn(2, ' ');
+        let date = NaiveDate::parse_from_str(header_fields.next().unwrap_or(""), "%Y/%m/%d")
+            .map_err(|err| err.to_string())?;
+        let description = header_fields.next().unwrap_or("").to_owned();
+        let mut tx = Transaction::new(date, description);
+        for line in lines.filter(|line| !line.trim().is_empty()) {
+            if let Some(tag) = line.trim().strip_prefix(';') {
+                let mut tag_fields = tag.splitn(2, ':');
+                let key = tag_fields.next().unwrap_or("").trim();
+                let value = tag_fields
+                    .next()
+                    .ok_or_else(|| format!("Invalid tag {}", line.trim()))?;
+                tx.add_tag(key, value.trim());
+                continue;
+            }
+            let invalid = || format!("Invalid posting {}", line.trim());
+            let (account, amounts) = split_posting(line.trim()).ok_or_else(invalid)?;
+            if let Some((_, balance)) = amounts.split_once(" = ") {
+                tx.add_assertion(account, parse_amount(balance).ok_or_else(invalid)?);
+            } else if let Some((amount, cost)) = amounts.split_once(" @@ ") {
+                let amount = parse_amount(amount).ok_or_else(invalid)?;
+                let cost = parse_amount(cost).ok_or_else(invalid)?;
+                let cost = if amount.1.is_sign_negative() {
+                    Amount(cost.0, -cost.1)
+                } else {
+                    cost
+                };
+                tx.add_converted_change(account, amount, cost);
+            } else {
+                tx.add_change(account, parse_amount(amounts).ok_or_else(invalid)?);
+            }
+        }
+        Ok(tx)
+    }
+}
+
+/// Splits a posting into the account and its amounts. Accounts can contain
+/// single spaces, so amounts are separated from them by at least two spaces
+/// or a tab.
+fn split_posting(posting: &str) -> Option<(&str, &str)> {
+    let separator = match (posting.find("  "), posting.find('\t')) {
+        (Some(spaces), Some(tab)) => spaces.min(tab),
+        (spaces, tab) => spaces.or(tab)?,
+    };
+    Some((&posting[..separator], posting[separator..].trim()))
+}
+
+/// Parses an amount written as the commodity followed by the number. The
+/// commodity can contain spaces when it's quoted, like `"Fund 2030" 10`.
+fn parse_amount(s: &str) -> Option<Amount> {
+    let (commodity, number) = s.trim().rsplit_once(char::is_whitespace)?;
+    let number = Decimal::from_str(number).ok()?;
+    Some(Amount(commodity.trim().to_owned(), number))
+}
+
+/// Parses the output of `ledger print` into transactions. Only the common
+/// syntax is understood, transactions with codes, notes, virtual postings or
+/// prices are refused.
+pub fn parse_ledger_print(output: &str) -> Result<Vec<Transaction>, String> {
+    let mut transactions = Vec::new();
+    let mut lines: Vec<&str> = Vec::new();
+    // Every unindented line starts a new transaction.
+    for line in output.lines().filter(|line| !line.trim().is_empty()) {
+        if !line.starts_with(char::is_whitespace) && !lines.is_empty() {
+            transactions.push(parse_printed_transaction(&lines)?);
+            lines.clear();
+        }
+        lines.push(line);
+    }
+    if !lines.is_empty() {
+        transactions.push(parse_printed_transaction(&lines)?);
+    }
+    Ok(transactions)
+}
+
+fn parse_printed_transaction(lines: &[&str]) -> Result<Transaction, String> {
+    let header = lines[0];
+    let unsupported = |line: &str| format!("Unsupported syntax in {}", line.trim());
+    let (date, rest) = header.split_once(' ').unwrap_or((header, ""));
+    let date = NaiveDate::parse_from_str(date, "%Y/%m/%d").map_err(|_| unsupported(header))?;
+    let description = rest.trim().trim_start_matches(['*', '!']).trim_start();
+    if description.starts_with('(') || description.contains(';') {
+        return Err(unsupported(header));
+    }
+    let mut tx = Transaction::new(date, description.to_owned());
+    let mut elided = None;
+    for line in &lines[1..] {
+        let posting = line.trim();
+        if let Some(tag) = posting.strip_prefix(';') {
+            let (key, value) = tag.split_once(':').ok_or_else(|| unsupported(line))?;
+            tx.add_tag(key.trim(), value.trim());
+            continue;
+        }
+        if posting.starts_with(['(', '[']) || posting.contains(|c| ";@{".contains(c)) {
+            return Err(unsupported(line));
+        }
+        let (account, amounts) = match split_posting(posting) {
+            Some(split) => split,
+            None if elided.is_none() => {
+                elided = Some(posting);
+                continue;
+            }
+            None => return Err(unsupported(line)),
+        };
+        let (change, balance) = match amounts.split_once(" = ") {
+            Some((change, balance)) => (change, Some(balance)),
+            None => (amounts, None),
+        };
+        let change = parse_printed_amount(change).ok_or_else(|| unsupported(line))?;
+        if change.1 != dec!(0) || balance.is_none() {
+            tx.add_change(account, change);
+        }
+        if let Some(balance) = balance {
+            let balance = parse_printed_amount(balance).ok_or_else(|| unsupported(line))?;
+            tx.add_assertion(account, balance);
+        }
+    }
+    // A posting without an amount balances the rest of the transaction.
+    if let Some(account) = elided {
+        tx.finalize(account, None);
+    }
+    Ok(tx)
+}
+
+/// Parses an amount in any of the styles ledger writes, like `$-1,000.00` or
+/// `-5,50 €`.
+fn parse_printed_amount(s: &str) -> Option<Amount> {
+    let (commodity, format) = CommodityFormat::parse(s)?;
+    let first_digit = s.find(|c: char| c.is_ascii_digit())?;
+    let last_digit = s.rfind(|c: char| c.is_ascii_digit())?;
+    let mut number: String = s[first_digit..=last_digit]
+        .chars()
+        .filter(|c| Some(*c) != format.thousands_separator)
+        .map(|c| if c == format.decimal_mark { '.' } else { c })
+        .collect();
+    if s.contains('-') {
+        number.insert(0, '-');
+    }
+    Some(Amount(commodity, Decimal::from_str(&number).ok()?))
+}
+
+/// Displays a transaction with dates in a custom format.
+pub struct TransactionDisplay<'a> {
+    tx: &'a Transaction,
+    date_format: &'a str,
+    commodity_formats: Option<&'a CommodityFormats>,
+    group_accounts: bool,
+    indent: &'a str,
+    separator: &'a str,
+    sign_display: SignDisplay,
+    precisions: Option<(&'a HashMap<String, u32>, u32)>,
+    amount_column: Option<usize>,
+}
+
+impl Transaction {
+    pub fn display<'a>(&'a self, date_format: &'a str) -> TransactionDisplay<'a> {
+        TransactionDisplay {
+            tx: self,
+            date_format,
+            commodity_formats: None,
+            group_accounts: false,
+            indent: "\t",
+            separator: "  ",
+            sign_display: SignDisplay::NegativeOnly,
+            precisions: None,
+            amount_column: None,
+        }
+    }
+}
+
+impl<'a> TransactionDisplay<'a> {
+    pub fn with_commodity_formats(
+        mut self,
+        commodity_formats: &'a CommodityFormats,
+    ) -> TransactionDisplay<'a> {
+        self.commodity_formats = Some(commodity_formats);
+        self
+    }
+
+    /// Ledger allows only a single amount per posting, so an account with
+    /// amounts in several currencies always gets a posting for each of them.
+    /// By default, postings adding to accounts come before the ones
+    /// subtracting from them. Grouping keeps all postings of an account
+    /// together instead, ordered by the account's first posting.
+    pub fn group_accounts(mut self, group_accounts: bool) -> TransactionDisplay<'a> {
+        self.group_accounts = group_accounts;
+        self
+    }
+
+    /// Sets the whitespace postings are indented with and the one separating
+    /// the longest account name from its amount. Defaults to a tab and two
+    /// spaces.
+    pub fn with_indentation(
+        mut self,
+        indent: &'a str,
+        separator: &'a str,
+    ) -> TransactionDisplay<'a> {
+        self.indent = indent;
+        self.separator = separator;
+        self
+    }
+
+    pub fn with_sign_display(mut self, sign_display: SignDisplay) -> TransactionDisplay<'a> {
+        self.sign_display = sign_display;
+        self
+    }
+
+    /// Pads amounts with trailing zeros to the decimal places of their
+    /// commodity, falling back to the default places for commodities
+    /// without any, so `€ 12` is written as `€ 12.00`.
+    pub fn pad_amounts(
+        mut self,
+        precisions: &'a HashMap<String, u32>,
+        default_precision: u32,
+    ) -> TransactionDisplay<'a> {
+        self.precisions = Some((precisions, default_precision));
+        self
+    }
+
+    /// Pads account names so that amounts start at the given column, counted
+    /// from zero with tabs stopping every 8 columns, to line up with the rest
+    /// of the journal. Accounts too long for the column push all amounts
+    /// further, keeping at least the separator before them.
+    pub fn with_amount_column(mut self, amount_column: Option<usize>) -> TransactionDisplay<'a> {
+        self.amount_column = amount_column;
+        self
+    }
+
+    pub fn date(&self) -> NaiveDate {
+        self.tx.date
+    }
+
+    pub fn transaction(&self) -> &Transaction {
+        self.tx
+    }
+
+    fn format_amount(&self, amount: &Amount) -> String {
+        let padded;
+        let amount = match self.precisions {
+            Some((precisions, default_precision)) => {
+                let places = precisions
+                    .get(&amount.0)
+                    .copied()
+                    .unwrap_or(default_precision);
+                padded = Amount(amount.0.clone(), pad(amount.1, places));
+                &padded
+            }
+            None => amount,
+        };
+        let plus = self.sign_display == SignDisplay::Always && amount.1 > dec!(0);
+        match self
+            .commodity_formats
+            .and_then(|formats| formats.get(&amount.0))
+        {
+            Some(format) if plus => {
+                format.place_commodity(&amount.0, &format!("+{}", format.format_number(amount.1)))
+            }
+            Some(format) => format.format(&amount.0, amount.1),
+            None if plus => format!("{} +{}", amount.0, amount.1),
+            None => amount.to_string(),
+        }
+    }
+}
+
+/// Adds trailing zeros to the number until it has the given decimal places.
+/// Numbers with more places are left as they are.
+pub fn pad(number: Decimal, places: u32) -> Decimal {
+    match places.checked_sub(number.scale()) {
+        Some(missing) if missing > 0 => number * Decimal::new(10i64.pow(missing), missing),
+        _ => number,
+    }
+}
+
+/// Returns the column at which the text ends when it starts at the given one.
+pub fn columns(text: &str, start: usize) -> usize {
+    text.chars().fold(start, |column, c| match c {
+        '\t' => (column / TAB_WIDTH + 1) * TAB_WIDTH,
+        _ => column + 1,
+    })
 }
 
 impl fmt::Display for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.display("%Y/%m/%d"))
+    }
+}
+
+impl<'a> fmt::Display for TransactionDisplay<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(
             f,
             "{} {}",
-            self.date.format("%Y-%m-%d").to_string(),
-            self.description
+            self.tx.date.format(self.date_format),
+            self.tx.description
         )?;
-        let amounts = self.amounts();
-        let max_account_name_len = self
-            .amounts()
+        for (key, value) in &self.tx.tags {
+            writeln!(f, "{}; {}: {}", self.indent, key, value)?;
+        }
+        let amounts = self.tx.amounts();
+        let max_account_name_len = amounts
             .iter()
-            .map(|(account, _)| account.chars().count())
+            .map(|(account, _)| *account)
+            .chain(
+                self.tx
+                    .assertions
+                    .iter()
+                    .map(|(account, _)| account.as_str()),
+            )
+            .chain(
+                self.tx
+                    .conversions
+                    .iter()
+                    .map(|(account, _, _)| account.as_str()),
+            )
+            .map(|account| account.chars().count())
             .max();
         if max_account_name_len.is_none() {
             return Ok(());
         }
-        let (mut credits, mut debits): (Vec<(&str, &Amount)>, Vec<(&str, &Amount)>) = amounts
+        let (mut credits, mut debits): (Vec<Posting>, Vec<Posting>) = amounts
             .iter()
             .partition(|amount_triple| (amount_triple.1).1 >= dec!(0));
         credits.sort_by_key(|amount_triple| amount_triple.0);
         debits.sort_by_key(|amount_triple| amount_triple.0);
-        for (account, amount) in credits.iter().chain(debits.iter()) {
-            let justification_spaces_count =
-                max_account_name_len.unwrap() - account.chars().count();
+        let mut postings: Vec<Posting> = credits.into_iter().chain(debits).collect();
+        if self.group_accounts {
+            let mut account_order: Vec<&str> = Vec::new();
+            for (account, _) in &postings {
+                if !account_order.contains(account) {
+                    account_order.push(account);
+                }
+            }
+            postings.sort_by_key(|(account, _)| account_order.iter().position(|a| a == account));
+        }
+        let mut name_width = max_account_name_len.unwrap();
+        if let Some(column) = self.amount_column {
+            let indent_end = columns(self.indent, 0);
+            let amount_start = columns(self.separator, indent_end + name_width);
+            name_width += column.saturating_sub(amount_start);
+        }
+        let justify = |account: &str| {
+            let justification_spaces_count = name_width - account.chars().count();
             let spaces: String = once(' ').cycle().take(justification_spaces_count).collect();
             let mut justified_account = account.to_string();
             justified_account.push_str(&spaces);
-            writeln!(f, "\t{}  {} {}", justified_account, amount.0, amount.1)?;
+            justified_account
+        };
+        for (account, amount) in postings {
+            writeln!(
+                f,
+                "{}{}{}{}",
+                self.indent,
+                justify(account),
+                self.separator,
+                self.format_amount(amount)
+            )?;
+        }
+        for (account, amount, cost) in &self.tx.conversions {
+            writeln!(
+                f,
+                "{}{}{}{} @@ {}",
+                self.indent,
+                justify(account),
+                self.separator,
+                self.format_amount(amount),
+                self.format_amount(&Amount(cost.0.clone(), cost.1.abs()))
+            )?;
+        }
+        // Assertions come last, so that they include the rest of the
+        // transaction. A posting without an amount would be a balance
+        // assignment, so a zero amount is written explicitly.
+        for (account, amount) in &self.tx.assertions {
+            let zero = Amount(amount.0.clone(), dec!(0));
+            writeln!(
+                f,
+                "{}{}{}{} = {}",
+                self.indent,
+                justify(account),
+                self.separator,
+                self.format_amount(&zero),
+                self.format_amount(amount)
+            )?;
         }
         Ok(())
     }
@@ -116,14 +717,16 @@ impl fmt::Display for Transaction {
 mod test {
     #[allow(unused_imports)]
     use super::*;
+    #[allow(unused_imports)]
+    use crate::commodity::CommodityFormat;
 
     #[test]
     fn tx_creation() {
         let tx = Transaction::new(
-            NaiveDate::from_ymd(2020, 01, 10),
+            NaiveDate::from_ymd(2020, 1, 10),
             "Test transaction".to_owned(),
         );
-        assert_eq!(tx.date, NaiveDate::from_ymd(2020, 01, 10));
+        assert_eq!(tx.date, NaiveDate::from_ymd(2020, 1, 10));
         assert_eq!(tx.description, "Test transaction".to_owned());
         assert_eq!(tx.changes.len(), 0);
     }
@@ -131,15 +734,15 @@ mod test {
     #[test]
     fn simple_changes() {
         let mut tx = Transaction::new(
-            NaiveDate::from_ymd(2020, 01, 10),
+            NaiveDate::from_ymd(2020, 1, 10),
             "Test transaction".to_owned(),
         );
-        tx.add_change(&"Expenses::Food", Amount("€".to_owned(), dec!(5.95)));
-        tx.add_change(&"Expenses::Hygiene", Amount("€".to_owned(), dec!(3.90)));
-        tx.add_change(&"Expenses::Hygiene", Amount("CZK".to_owned(), dec!(25)));
-        tx.add_change(&"Expenses::Hygiene", Amount("CZK".to_owned(), dec!(13)));
-        tx.add_change(&"Expenses::Food", Amount("€".to_owned(), dec!(2)));
-        tx.add_change(&"Expenses::Food", Amount("CZK".to_owned(), dec!(120)));
+        tx.add_change("Expenses::Food", Amount("€".to_owned(), dec!(5.95)));
+        tx.add_change("Expenses::Hygiene", Amount("€".to_owned(), dec!(3.90)));
+        tx.add_change("Expenses::Hygiene", Amount("CZK".to_owned(), dec!(25)));
+        tx.add_change("Expenses::Hygiene", Amount("CZK".to_owned(), dec!(13)));
+        tx.add_change("Expenses::Food", Amount("€".to_owned(), dec!(2)));
+        tx.add_change("Expenses::Food", Amount("CZK".to_owned(), dec!(120)));
         assert_eq!(
             tx.changes["Expenses::Food"],
             vec![
@@ -163,21 +766,148 @@ mod test {
         );
     }
 
+    #[test]
+    fn cancelling_changes() {
+        let mut tx = Transaction::new(
+            NaiveDate::from_ymd(2020, 1, 10),
+            "Test transaction".to_owned(),
+        );
+        tx.add_change("Expenses:Food", Amount("€".to_owned(), dec!(5)));
+        tx.add_change("Expenses:Food", Amount("CZK".to_owned(), dec!(120)));
+        tx.add_change("Expenses:Food", Amount("€".to_owned(), dec!(-5.00)));
+        tx.add_change("Assets:Cash", Amount("CZK".to_owned(), dec!(0)));
+        assert_eq!(
+            tx.changes["Expenses:Food"],
+            vec![Amount("CZK".to_owned(), dec!(120))]
+        );
+        assert!(!tx.changes.contains_key("Assets:Cash"));
+
+        tx.add_change("Expenses:Food", Amount("CZK".to_owned(), dec!(-120)));
+        assert!(tx.changes.is_empty());
+        assert_eq!(tx.to_string(), "2020/01/10 Test transaction\n");
+    }
+
+    #[test]
+    fn opposing_changes() {
+        let mut tx = Transaction::new(
+            NaiveDate::from_ymd(2020, 1, 10),
+            "Test transaction".to_owned(),
+        );
+        tx.add_change("Expenses:Food", Amount("€".to_owned(), dec!(5)));
+        tx.add_change("Assets:Cash", Amount("€".to_owned(), dec!(-5)));
+        let earlier = tx.clone();
+        tx.add_change("Expenses:Food", Amount("€".to_owned(), dec!(2)));
+        tx.add_change("Expenses:Food", Amount("CZK".to_owned(), dec!(-20)));
+        tx.add_change("Assets:Cash", Amount("€".to_owned(), dec!(-2)));
+        assert_eq!(tx.opposing_changes(&earlier), vec![]);
+
+        let earlier = tx.clone();
+        tx.add_change("Expenses:Food", Amount("€".to_owned(), dec!(-7)));
+        tx.add_change("Assets:Cash", Amount("€".to_owned(), dec!(1)));
+        assert_eq!(
+            tx.opposing_changes(&earlier),
+            vec![
+                ("Assets:Cash".to_owned(), "€".to_owned()),
+                ("Expenses:Food".to_owned(), "€".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn changes_since() {
+        let mut tx = Transaction::new(NaiveDate::from_ymd(2020, 1, 10), "Lidl".to_owned());
+        tx.add_change("Expenses:Food", Amount("€".to_owned(), dec!(5)));
+        tx.add_change("Assets:Cash", Amount("€".to_owned(), dec!(-5)));
+        let earlier = tx.clone();
+        tx.add_change("Expenses:Food", Amount("€".to_owned(), dec!(-5)));
+        tx.add_change("Expenses:Food", Amount("CZK".to_owned(), dec!(20)));
+        assert_eq!(
+            tx.changes_since(&earlier),
+            vec![
+                (
+                    "Expenses:Food".to_owned(),
+                    Amount("CZK".to_owned(), dec!(20))
+                ),
+                ("Expenses:Food".to_owned(), Amount("€".to_owned(), dec!(-5)))
+            ]
+        );
+        assert_eq!(tx.changes_since(&tx.clone()), vec![]);
+
+        let earlier = tx.clone();
+        tx.add_converted_change(
+            "Expenses:Food",
+            Amount("CZK".to_owned(), dec!(-250)),
+            Amount("€".to_owned(), dec!(-10)),
+        );
+        assert_eq!(
+            tx.changes_since(&earlier),
+            vec![(
+                "Expenses:Food".to_owned(),
+                Amount("CZK".to_owned(), dec!(-250))
+            )]
+        );
+    }
+
+    #[test]
+    fn odd_cent_splits() {
+        for total in &[dec!(0.01), dec!(10.01), dec!(-10.01), dec!(0.001)] {
+            let mut tx = Transaction::new(NaiveDate::from_ymd(2020, 1, 10), "Split".to_owned());
+            tx.add_split_change("Expenses", "Debts:Peter", Amount("€".to_owned(), *total), 2);
+            let share = |account: &str| tx.changes.get(account).map_or(dec!(0), |am| am[0].1);
+            let (first, second) = (share("Expenses"), share("Debts:Peter"));
+            assert_eq!(first + second, *total);
+            assert!((first - second).abs() <= dec!(0.01));
+        }
+    }
+
+    #[test]
+    fn currency_precision_splits() {
+        let mut tx = Transaction::new(NaiveDate::from_ymd(2020, 1, 10), "Split".to_owned());
+        tx.add_split_change(
+            "Expenses",
+            "Debts:Peter",
+            Amount("JPY".to_owned(), dec!(1001)),
+            0,
+        );
+        tx.add_split_change(
+            "Expenses",
+            "Debts:Peter",
+            Amount("BHD".to_owned(), dec!(1.001)),
+            3,
+        );
+        assert_eq!(
+            tx.changes["Expenses"],
+            vec![
+                Amount("BHD".to_owned(), dec!(0.500)),
+                Amount("JPY".to_owned(), dec!(500))
+            ]
+        );
+        assert_eq!(
+            tx.changes["Debts:Peter"],
+            vec![
+                Amount("BHD".to_owned(), dec!(0.501)),
+                Amount("JPY".to_owned(), dec!(501))
+            ]
+        );
+    }
+
     #[test]
     fn split_changes() {
         let mut tx = Transaction::new(
-            NaiveDate::from_ymd(2020, 01, 10),
+            NaiveDate::from_ymd(2020, 1, 10),
             "Test transaction".to_owned(),
         );
         tx.add_split_change(
             "Expenses::Food",
             "Debts::Peter",
             Amount("€".to_owned(), dec!(7)),
+            2,
         );
         tx.add_split_change(
             "Expenses::Food",
             "Debts::Peter",
             Amount("CZK".to_owned(), dec!(120)),
+            2,
         );
         tx.add_change("Expenses::Food", Amount("€".to_owned(), dec!(2)));
         assert_eq!(
@@ -203,17 +933,505 @@ mod test {
         )
     }
 
+    #[test]
+    fn transfers() {
+        let mut tx = Transaction::new(NaiveDate::from_ymd(2020, 1, 10), "ATM".to_owned());
+        tx.add_transfer(
+            "Assets:Checking",
+            "Assets:Cash",
+            Amount("€".to_owned(), dec!(50)),
+        );
+        assert_eq!(
+            tx.changes["Assets:Checking"],
+            vec![Amount("€".to_owned(), dec!(-50))]
+        );
+        assert_eq!(
+            tx.changes["Assets:Cash"],
+            vec![Amount("€".to_owned(), dec!(50))]
+        );
+        assert!(tx.is_balanced(None));
+    }
+
+    #[test]
+    fn named_split_changes() {
+        let mut tx = Transaction::new(
+            NaiveDate::from_ymd(2020, 1, 10),
+            "Test transaction".to_owned(),
+        );
+        tx.add_named_split_change(
+            "Expenses::Food",
+            &["anna", "bob", "cara"],
+            Amount("€".to_owned(), dec!(60)),
+            DEBTS_PREFIX,
+            2,
+        );
+        assert_eq!(
+            tx.changes["Expenses::Food"],
+            vec![Amount("€".to_owned(), dec!(15))]
+        );
+        assert_eq!(
+            tx.changes["Debts:Anna"],
+            vec![Amount("€".to_owned(), dec!(15))]
+        );
+        assert_eq!(
+            tx.changes["Debts:Bob"],
+            vec![Amount("€".to_owned(), dec!(15))]
+        );
+        assert_eq!(
+            tx.changes["Debts:Cara"],
+            vec![Amount("€".to_owned(), dec!(15))]
+        );
+
+        let mut tx = Transaction::new(
+            NaiveDate::from_ymd(2020, 1, 10),
+            "Test transaction".to_owned(),
+        );
+        tx.add_named_split_change(
+            "Expenses::Food",
+            &["anna", "bob"],
+            Amount("€".to_owned(), dec!(10)),
+            DEBTS_PREFIX,
+            2,
+        );
+        assert_eq!(
+            tx.changes["Expenses::Food"],
+            vec![Amount("€".to_owned(), dec!(3.34))]
+        );
+        assert_eq!(
+            tx.changes["Debts:Anna"],
+            vec![Amount("€".to_owned(), dec!(3.33))]
+        );
+        assert_eq!(tx.balance(), vec![Amount("€".to_owned(), dec!(10))]);
+    }
+
+    #[test]
+    fn weighted_changes() {
+        let mut tx = Transaction::new(
+            NaiveDate::from_ymd(2020, 1, 10),
+            "Test transaction".to_owned(),
+        );
+        tx.add_weighted_change(
+            &[
+                ("Debts:Anna".to_owned(), dec!(30)),
+                ("Debts:Bob".to_owned(), dec!(20)),
+                ("Expenses:Food".to_owned(), dec!(10)),
+            ],
+            Amount("€".to_owned(), dec!(72)),
+            2,
+        );
+        assert_eq!(
+            tx.changes["Debts:Anna"],
+            vec![Amount("€".to_owned(), dec!(36))]
+        );
+        assert_eq!(
+            tx.changes["Debts:Bob"],
+            vec![Amount("€".to_owned(), dec!(24))]
+        );
+        assert_eq!(
+            tx.changes["Expenses:Food"],
+            vec![Amount("€".to_owned(), dec!(12))]
+        );
+
+        let mut tx = Transaction::new(
+            NaiveDate::from_ymd(2020, 1, 10),
+            "Test transaction".to_owned(),
+        );
+        tx.add_weighted_change(
+            &[
+                ("Debts:Anna".to_owned(), dec!(10)),
+                ("Debts:Bob".to_owned(), dec!(10)),
+                ("Expenses:Food".to_owned(), dec!(10)),
+            ],
+            Amount("€".to_owned(), dec!(35.50)),
+            2,
+        );
+        assert_eq!(
+            tx.changes["Debts:Anna"],
+            vec![Amount("€".to_owned(), dec!(11.84))]
+        );
+        assert_eq!(
+            tx.changes["Debts:Bob"],
+            vec![Amount("€".to_owned(), dec!(11.83))]
+        );
+        assert_eq!(tx.balance(), vec![Amount("€".to_owned(), dec!(35.50))]);
+    }
+
+    #[test]
+    fn debt_account_names() {
+        assert_eq!(debt_account(DEBTS_PREFIX, "anna"), "Debts:Anna");
+        assert_eq!(debt_account(DEBTS_PREFIX, "Bob"), "Debts:Bob");
+        assert_eq!(debt_account(DEBTS_PREFIX, "édouard"), "Debts:Édouard");
+        assert_eq!(counterparty(DEBTS_PREFIX, "Debts:Pete"), Some("Pete"));
+        assert_eq!(counterparty(DEBTS_PREFIX, "Debts:Trip:Pete"), Some("Pete"));
+        assert_eq!(counterparty(DEBTS_PREFIX, "Debts:"), None);
+        assert_eq!(counterparty(DEBTS_PREFIX, "Expenses:Food"), None);
+        assert_eq!(
+            debt_account("Liabilities:Owed:", "anna"),
+            "Liabilities:Owed:Anna"
+        );
+        assert_eq!(
+            counterparty("Liabilities:Owed:", "Liabilities:Owed:Anna"),
+            Some("Anna")
+        );
+        assert_eq!(counterparty("Liabilities:Owed:", "Debts:Anna"), None);
+    }
+
+    #[test]
+    fn custom_date_format_output() {
+        let mut tx = Transaction::new(
+            NaiveDate::from_ymd(2020, 2, 27),
+            "Test transaction".to_owned(),
+        );
+        tx.add_change("Expenses:Food", Amount("€".to_owned(), dec!(5)));
+        assert!(tx.to_string().starts_with("2020/02/27 Test transaction\n"));
+        assert!(tx
+            .display("%d.%m.%Y")
+            .to_string()
+            .starts_with("27.02.2020 Test transaction\n"));
+    }
+
+    #[test]
+    fn commodity_formatted_output() {
+        let mut tx = Transaction::new(
+            NaiveDate::from_ymd(2020, 2, 27),
+            "Test transaction".to_owned(),
+        );
+        tx.add_change("Expenses:Food", Amount("€".to_owned(), dec!(1234.5)));
+        tx.add_change("Expenses:Rent", Amount("$".to_owned(), dec!(12)));
+        tx.add_change("Expenses:Other", Amount("CZK".to_owned(), dec!(5)));
+        let mut formats = CommodityFormats::new();
+        formats.insert(
+            "€".to_owned(),
+            CommodityFormat::parse("1.000,00 €").unwrap().1,
+        );
+        formats.insert(
+            "$".to_owned(),
+            CommodityFormat::parse("$1,000.00").unwrap().1,
+        );
+        assert_eq!(
+            tx.display("%Y-%m-%d")
+                .with_commodity_formats(&formats)
+                .to_string(),
+            "2020-02-27 Test transaction\n\
+             \tExpenses:Food   1.234,50 €\n\
+             \tExpenses:Other  CZK 5\n\
+             \tExpenses:Rent   $12.00\n"
+        );
+    }
+
+    #[test]
+    fn signed_output() {
+        let mut tx = Transaction::new(NaiveDate::from_ymd(2020, 3, 31), "Salary".to_owned());
+        tx.add_change("Assets:Checking", Amount("€".to_owned(), dec!(1500)));
+        tx.add_change("Income:Salary", Amount("€".to_owned(), dec!(-1500)));
+        tx.add_change("Assets:Pension", Amount("$".to_owned(), dec!(25)));
+        tx.add_change("Income:Salary", Amount("$".to_owned(), dec!(-25)));
+        assert_eq!(
+            tx.display("%Y-%m-%d")
+                .with_sign_display(SignDisplay::NegativeOnly)
+                .to_string(),
+            "2020-03-31 Salary\n\
+             \tAssets:Checking  € 1500\n\
+             \tAssets:Pension   $ 25\n\
+             \tIncome:Salary    $ -25\n\
+             \tIncome:Salary    € -1500\n"
+        );
+
+        let mut formats = CommodityFormats::new();
+        formats.insert(
+            "$".to_owned(),
+            CommodityFormat::parse("$1,000.00").unwrap().1,
+        );
+        assert_eq!(
+            tx.display("%Y-%m-%d")
+                .with_commodity_formats(&formats)
+                .with_sign_display(SignDisplay::Always)
+                .to_string(),
+            "2020-03-31 Salary\n\
+             \tAssets:Checking  € +1500\n\
+             \tAssets:Pension   $+25.00\n\
+             \tIncome:Salary    $-25.00\n\
+             \tIncome:Salary    € -1500\n"
+        );
+        let signed = tx
+            .display("%Y/%m/%d")
+            .with_sign_display(SignDisplay::Always)
+            .to_string();
+        assert_eq!(signed.parse::<Transaction>().unwrap(), tx);
+    }
+
+    #[test]
+    fn aligned_output() {
+        let mut tx = Transaction::new(NaiveDate::from_ymd(2020, 3, 31), "Lunch".to_owned());
+        tx.add_change("Expenses:Food", Amount("€".to_owned(), dec!(12)));
+        tx.finalize("Assets:Cash", None);
+        assert_eq!(
+            tx.display("%Y-%m-%d")
+                .with_indentation("    ", "  ")
+                .with_amount_column(Some(24))
+                .to_string(),
+            "2020-03-31 Lunch\n    Expenses:Food       € 12\n    Assets:Cash         € -12\n"
+        );
+        // A tab indents to column 8.
+        assert_eq!(
+            tx.display("%Y-%m-%d")
+                .with_amount_column(Some(24))
+                .to_string(),
+            "2020-03-31 Lunch\n\tExpenses:Food   € 12\n\tAssets:Cash     € -12\n"
+        );
+        assert_eq!(
+            tx.display("%Y-%m-%d")
+                .with_amount_column(Some(10))
+                .to_string(),
+            tx.display("%Y-%m-%d").to_string()
+        );
+        assert_eq!(columns("\tab\tc", 0), 17);
+        assert_eq!(columns("  ", 6), 8);
+    }
+
+    #[test]
+    fn padded_output() {
+        let mut tx = Transaction::new(NaiveDate::from_ymd(2020, 3, 31), "Lunch".to_owned());
+        tx.add_change("Expenses:Food", Amount("€".to_owned(), dec!(12)));
+        tx.add_change("Expenses:Food", Amount("JPY".to_owned(), dec!(500)));
+        tx.add_change("Expenses:Tips", Amount("€".to_owned(), dec!(0.125)));
+        tx.add_change("Expenses:Drinks", Amount("€".to_owned(), dec!(12.5)));
+        tx.finalize("Assets:Cash", None);
+        let mut precisions = HashMap::new();
+        precisions.insert("JPY".to_owned(), 0);
+        assert_eq!(
+            tx.display("%Y-%m-%d")
+                .pad_amounts(&precisions, 2)
+                .to_string(),
+            "2020-03-31 Lunch\n\
+             \tExpenses:Drinks  € 12.50\n\
+             \tExpenses:Food    JPY 500\n\
+             \tExpenses:Food    € 12.00\n\
+             \tExpenses:Tips    € 0.125\n\
+             \tAssets:Cash      JPY -500\n\
+             \tAssets:Cash      € -24.625\n"
+        );
+        assert_eq!(pad(dec!(-12), 2).to_string(), "-12.00");
+        assert_eq!(pad(dec!(12.5), 2), dec!(12.5));
+    }
+
+    #[test]
+    fn duplicates() {
+        let mut tx = Transaction::new(NaiveDate::from_ymd(2020, 1, 10), "Lidl".to_owned());
+        tx.add_change("Expenses:Food", Amount("€".to_owned(), dec!(5.50)));
+        tx.add_change("Expenses:Food", Amount("CZK".to_owned(), dec!(100)));
+        tx.finalize("Assets:Checking", None);
+
+        let mut other = Transaction::new(NaiveDate::from_ymd(2020, 1, 10), "Lidl ".to_owned());
+        other.add_change("Assets:Checking", Amount("CZK".to_owned(), dec!(-100)));
+        other.add_change("Assets:Checking", Amount("€".to_owned(), dec!(-5.5)));
+        other.add_change("Expenses:Food", Amount("CZK".to_owned(), dec!(100.00)));
+        other.add_change("Expenses:Food", Amount("€".to_owned(), dec!(5.5)));
+        assert!(tx.is_duplicate_of(&other));
+
+        other.add_change("Expenses:Food", Amount("€".to_owned(), dec!(1)));
+        assert!(!tx.is_duplicate_of(&other));
+        other.date = NaiveDate::from_ymd(2020, 1, 11);
+        other.add_change("Expenses:Food", Amount("€".to_owned(), dec!(-1)));
+        assert!(!tx.is_duplicate_of(&other));
+    }
+
+    #[test]
+    fn parsing() {
+        let mut tx = Transaction::new(
+            NaiveDate::from_ymd(2020, 1, 10),
+            "Test transaction".to_owned(),
+        );
+        tx.add_change("Expenses:Food", Amount("€".to_owned(), dec!(7.50)));
+        tx.add_change("Expenses:Food", Amount("CZK".to_owned(), dec!(500)));
+        tx.add_change("Assets:Cash", Amount("€".to_owned(), dec!(-2)));
+        assert_eq!(tx.to_string().parse::<Transaction>().unwrap(), tx);
+        tx.add_tag("trip", "Japan 2024");
+        assert_eq!(tx.to_string().parse::<Transaction>().unwrap(), tx);
+        tx.add_change(
+            "Assets:Broker",
+            Amount("\"Fund 2030\"".to_owned(), dec!(10)),
+        );
+        assert_eq!(tx.to_string().parse::<Transaction>().unwrap(), tx);
+
+        let empty = Transaction::new(NaiveDate::from_ymd(2020, 1, 10), "".to_owned());
+        assert_eq!(empty.to_string().parse::<Transaction>().unwrap(), empty);
+        assert!("2020/01/10 Test\n\tExpenses € 5 x"
+            .parse::<Transaction>()
+            .is_err());
+        assert!("10.1.2020 Test".parse::<Transaction>().is_err());
+
+        let mut tx = Transaction::new(NaiveDate::from_ymd(2020, 1, 10), "Trip".to_owned());
+        tx.add_tag("trip", "Prague");
+        tx.add_change("Expenses:Food and Drink", Amount("€".to_owned(), dec!(5)));
+        tx.add_converted_change(
+            "Expenses:Public Transport",
+            Amount("CZK".to_owned(), dec!(250)),
+            Amount("€".to_owned(), dec!(10)),
+        );
+        tx.add_change("Assets:Checking Account", Amount("€".to_owned(), dec!(-15)));
+        tx.add_assertion("Assets:Checking Account", Amount("€".to_owned(), dec!(85)));
+        assert_eq!(tx.to_string().parse::<Transaction>().unwrap(), tx);
+    }
+
+    #[test]
+    fn ledger_print_parsing() {
+        let output = "2020/03/01 * Lidl\n    \
+                      ; trip: Japan 2024\n    \
+                      Expenses:Food and Drink                  €7.50\n    \
+                      Expenses:Food and Drink               CZK 1,500\n    \
+                      Assets:Cash\n\n\
+                      2020/03/02 Exchange\n    \
+                      Assets:Cash\t-5,50 €\n    \
+                      Assets:Checking                          $-12.00 = $100.00\n    \
+                      Equity\n";
+        let mut lidl = Transaction::new(NaiveDate::from_ymd(2020, 3, 1), "Lidl".to_owned());
+        lidl.add_tag("trip", "Japan 2024");
+        lidl.add_change("Expenses:Food and Drink", Amount("€".to_owned(), dec!(7.5)));
+        lidl.add_change(
+            "Expenses:Food and Drink",
+            Amount("CZK".to_owned(), dec!(1500)),
+        );
+        lidl.add_change("Assets:Cash", Amount("€".to_owned(), dec!(-7.5)));
+        lidl.add_change("Assets:Cash", Amount("CZK".to_owned(), dec!(-1500)));
+        let mut exchange = Transaction::new(NaiveDate::from_ymd(2020, 3, 2), "Exchange".to_owned());
+        exchange.add_change("Assets:Cash", Amount("€".to_owned(), dec!(-5.5)));
+        exchange.add_change("Assets:Checking", Amount("$".to_owned(), dec!(-12)));
+        exchange.add_assertion("Assets:Checking", Amount("$".to_owned(), dec!(100)));
+        exchange.finalize("Equity", None);
+        assert_eq!(parse_ledger_print(output).unwrap(), vec![lidl, exchange]);
+        assert_eq!(parse_ledger_print("").unwrap(), vec![]);
+
+        let unsupported = [
+            "2020/03/01 (42) Lidl\n    Expenses  €5\n    Assets\n",
+            "2020/03/01 Lidl  ; receipt lost\n    Expenses  €5\n    Assets\n",
+            "2020/03/01=2020/03/05 Lidl\n    Expenses  €5\n    Assets\n",
+            "2020/03/01 Lidl\n    (Budget:Food)  €-5\n    Expenses  €5\n    Assets\n",
+            "2020/03/01 Lidl\n    Expenses  10 CZK @ €0.04\n    Assets\n",
+            "2020/03/01 Lidl\n    Expenses  €5  ; bread\n    Assets\n",
+            "2020/03/01 Lidl\n    Expenses\n    Assets\n",
+        ];
+        for tx in unsupported.iter() {
+            assert!(parse_ledger_print(tx).is_err(), "{}", tx);
+        }
+    }
+
+    #[test]
+    fn converted_output() {
+        let mut tx = Transaction::new(NaiveDate::from_ymd(2020, 1, 10), "Prague".to_owned());
+        tx.add_converted_change(
+            "Expenses:Travel",
+            Amount("CZK".to_owned(), dec!(250)),
+            Amount("€".to_owned(), dec!(10)),
+        );
+        tx.add_converted_change(
+            "Income:Refunds",
+            Amount("CZK".to_owned(), dec!(-50)),
+            Amount("€".to_owned(), dec!(-2)),
+        );
+        assert_eq!(tx.unbalanced(), vec![Amount("€".to_owned(), dec!(8))]);
+        tx.finalize("Assets:Cash", None);
+        assert_eq!(
+            tx.to_string(),
+            "2020/01/10 Prague\n\
+             \tAssets:Cash      € -8\n\
+             \tExpenses:Travel  CZK 250 @@ € 10\n\
+             \tIncome:Refunds   CZK -50 @@ € 2\n"
+        );
+        assert_eq!(tx.to_string().parse::<Transaction>().unwrap(), tx);
+    }
+
+    #[test]
+    fn tagged_output() {
+        let mut tx = Transaction::new(NaiveDate::from_ymd(2024, 4, 2), "Ramen".to_owned());
+        tx.add_change("Expenses:Food", Amount("JPY".to_owned(), dec!(1200)));
+        tx.add_change("Assets:Cash", Amount("JPY".to_owned(), dec!(-1200)));
+        tx.add_tag("trip", "Japan2024");
+        tx.add_tag("with", "Anna");
+        assert_eq!(
+            tx.to_string(),
+            "2024/04/02 Ramen\n\
+             \t; trip: Japan2024\n\
+             \t; with: Anna\n\
+             \tExpenses:Food  JPY 1200\n\
+             \tAssets:Cash    JPY -1200\n"
+        );
+    }
+
+    #[test]
+    fn balance_assertions() {
+        let mut tx = Transaction::new(NaiveDate::from_ymd(2024, 4, 2), "ATM".to_owned());
+        tx.add_change("Assets:Cash", Amount("€".to_owned(), dec!(100)));
+        tx.add_change("Assets:Checking", Amount("€".to_owned(), dec!(-100)));
+        tx.add_assertion("Assets:Cash", Amount("€".to_owned(), dec!(120)));
+        tx.add_assertion("Assets:Cash", Amount("€".to_owned(), dec!(150)));
+        assert_eq!(tx.unbalanced(), vec![]);
+        assert_eq!(
+            tx.to_string(),
+            "2024/04/02 ATM\n\
+             \tAssets:Cash      € 100\n\
+             \tAssets:Checking  € -100\n\
+             \tAssets:Cash      € 0 = € 150\n"
+        );
+        assert_eq!(tx.to_string().parse::<Transaction>().unwrap(), tx);
+    }
+
+    #[test]
+    fn custom_indentation() {
+        let mut tx = Transaction::new(NaiveDate::from_ymd(2024, 4, 2), "Ramen".to_owned());
+        tx.add_change("Expenses:Food", Amount("JPY".to_owned(), dec!(1200)));
+        tx.add_change("Assets:Cash", Amount("JPY".to_owned(), dec!(-1200)));
+        tx.add_tag("trip", "Japan2024");
+        assert_eq!(
+            tx.display("%Y/%m/%d")
+                .with_indentation("    ", "\t")
+                .to_string(),
+            "2024/04/02 Ramen\n    \
+             ; trip: Japan2024\n    \
+             Expenses:Food\tJPY 1200\n    \
+             Assets:Cash  \tJPY -1200\n"
+        );
+    }
+
+    #[test]
+    fn grouped_output() {
+        let mut tx = Transaction::new(NaiveDate::from_ymd(2020, 2, 27), "Exchange".to_owned());
+        tx.add_change("Assets:Cash", Amount("CZK".to_owned(), dec!(250)));
+        tx.add_change("Assets:Cash", Amount("€".to_owned(), dec!(-10)));
+        tx.add_change("Expenses:Fees", Amount("CZK".to_owned(), dec!(5)));
+        tx.add_change("Expenses:Fees", Amount("€".to_owned(), dec!(10)));
+        tx.add_change("Income:Exchange", Amount("CZK".to_owned(), dec!(-255)));
+        assert_eq!(
+            tx.display("%Y-%m-%d").to_string(),
+            "2020-02-27 Exchange\n\
+             \tAssets:Cash      CZK 250\n\
+             \tExpenses:Fees    CZK 5\n\
+             \tExpenses:Fees    € 10\n\
+             \tAssets:Cash      € -10\n\
+             \tIncome:Exchange  CZK -255\n"
+        );
+        assert_eq!(
+            tx.display("%Y-%m-%d").group_accounts(true).to_string(),
+            "2020-02-27 Exchange\n\
+             \tAssets:Cash      CZK 250\n\
+             \tAssets:Cash      € -10\n\
+             \tExpenses:Fees    CZK 5\n\
+             \tExpenses:Fees    € 10\n\
+             \tIncome:Exchange  CZK -255\n"
+        );
+    }
+
     #[test]
     fn finalization() {
         let mut tx = Transaction::new(
-            NaiveDate::from_ymd(2020, 01, 10),
+            NaiveDate::from_ymd(2020, 1, 10),
             "Test transaction".to_owned(),
         );
         tx.add_change("Expenses::Food", Amount("€".to_owned(), dec!(7)));
         tx.add_change("Expenses::Food", Amount("CZK".to_owned(), dec!(500)));
         tx.add_change("Assets::Cash", Amount("€".to_owned(), dec!(-2)));
         tx.add_change("Assets::Cash", Amount("CZK".to_owned(), dec!(-400)));
-        tx.finalize("Assets::Account");
+        tx.finalize("Assets::Account", None);
         assert_eq!(
             tx.changes["Assets::Account"],
             vec![
@@ -228,5 +1446,50 @@ mod test {
                 Amount("€".to_owned(), dec!(0))
             ]
         );
+        assert!(tx.unbalanced().is_empty());
+        assert!(tx.is_balanced(None));
+
+        let finalized = tx.clone();
+        tx.finalize("Assets::Account", None);
+        tx.finalize("Assets::Other", None);
+        assert_eq!(tx, finalized);
+    }
+
+    #[test]
+    fn currency_finalization() {
+        let mut tx = Transaction::new(
+            NaiveDate::from_ymd(2020, 1, 10),
+            "Test transaction".to_owned(),
+        );
+        tx.add_change("Expenses::Food", Amount("€".to_owned(), dec!(7)));
+        tx.add_change("Expenses::Food", Amount("PTS".to_owned(), dec!(50)));
+        tx.finalize("Assets::Account", Some("€"));
+        assert_eq!(
+            tx.changes["Assets::Account"],
+            vec![Amount("€".to_owned(), dec!(-7))]
+        );
+        assert_eq!(tx.unbalanced(), vec![Amount("PTS".to_owned(), dec!(50))]);
+        assert!(tx.is_balanced(Some("€")));
+        assert!(!tx.is_balanced(Some("PTS")));
+        assert!(!tx.is_balanced(None));
+
+        tx.finalize("Assets::Account", Some("€"));
+        assert_eq!(
+            tx.changes["Assets::Account"],
+            vec![Amount("€".to_owned(), dec!(-7))]
+        );
+    }
+
+    #[test]
+    fn unbalanced_amounts() {
+        let mut tx = Transaction::new(
+            NaiveDate::from_ymd(2020, 1, 10),
+            "Test transaction".to_owned(),
+        );
+        tx.add_change("Expenses::Food", Amount("€".to_owned(), dec!(7)));
+        tx.add_change("Expenses::Food", Amount("CZK".to_owned(), dec!(500)));
+        tx.add_change("Assets::Cash", Amount("€".to_owned(), dec!(-7)));
+        assert_eq!(tx.unbalanced(), vec![Amount("CZK".to_owned(), dec!(500))]);
+        assert_eq!(tx.unbalanced()[0].to_string(), "CZK 500");
     }
 }
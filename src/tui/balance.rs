@@ -0,0 +1,76 @@
+use crate::commodity::CommodityFormats;
+use crate::transaction::Amount;
+
+/// Lays out amounts as a table, with commodities left-aligned in the first
+/// column and numbers right-aligned in the second one. Rows are indented and
+/// separated in the same way as postings in the transaction preview.
+pub fn balance_table(
+    amounts: &[Amount],
+    formats: &CommodityFormats,
+    indent: &str,
+    separator: &str,
+) -> Vec<String> {
+    let rows: Vec<(&str, String)> = amounts
+        .iter()
+        .map(|Amount(commodity, number)| {
+            let number = match formats.get(commodity) {
+                Some(format) => format.format_number(*number),
+                None => number.to_string(),
+            };
+            (commodity.as_str(), number)
+        })
+        .collect();
+    let commodity_width = rows
+        .iter()
+        .map(|(commodity, _)| commodity.chars().count())
+        .max()
+        .unwrap_or(0);
+    let number_width = rows
+        .iter()
+        .map(|(_, number)| number.chars().count())
+        .max()
+        .unwrap_or(0);
+    rows.iter()
+        .map(|(commodity, number)| {
+            format!(
+                "{}{:<cw$}{}{:>nw$}",
+                indent,
+                commodity,
+                separator,
+                number,
+                cw = commodity_width,
+                nw = number_width
+            )
+        })
+        .collect()
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use crate::commodity::CommodityFormat;
+    #[allow(unused_imports)]
+    use rust_decimal_macros::*;
+
+    #[test]
+    fn aligned_table() {
+        let amounts = vec![
+            Amount("CZK".to_owned(), dec!(-1500)),
+            Amount("€".to_owned(), dec!(7.5)),
+        ];
+        assert_eq!(
+            balance_table(&amounts, &CommodityFormats::new(), "\t", "  "),
+            vec!["\tCZK  -1500", "\t€      7.5"]
+        );
+
+        let mut formats = CommodityFormats::new();
+        let (commodity, format) = CommodityFormat::parse("1.000,00 €").unwrap();
+        formats.insert(commodity, format);
+        assert_eq!(
+            balance_table(&amounts, &formats, "    ", " "),
+            vec!["    CZK -1500", "    €    7,50"]
+        );
+        assert!(balance_table(&[], &formats, "\t", "  ").is_empty());
+    }
+}
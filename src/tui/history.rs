@@ -0,0 +1,73 @@
+/// Earlier and undone states of the transaction being edited, for the undo
+/// and redo commands.
+pub struct History<T> {
+    undo: Vec<T>,
+    redo: Vec<T>,
+}
+
+impl<T> History<T> {
+    pub fn new() -> History<T> {
+        History {
+            undo: Vec::new(),
+            redo: Vec::new(),
+        }
+    }
+
+    /// Records the state before a change. The undone states can't be redone
+    /// after that.
+    pub fn record(&mut self, state: T) {
+        self.undo.push(state);
+        self.redo.clear();
+    }
+
+    /// Returns the state before the last change, keeping the current one for
+    /// redoing it, or `None` when there is nothing to undo.
+    pub fn undo(&mut self, current: T) -> Option<T> {
+        let earlier = self.undo.pop()?;
+        self.redo.push(current);
+        Some(earlier)
+    }
+
+    /// Returns the state the last undo went back from, or `None` when there
+    /// is nothing to redo.
+    pub fn redo(&mut self, current: T) -> Option<T> {
+        let later = self.redo.pop()?;
+        self.undo.push(current);
+        Some(later)
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn undo_and_redo() {
+        let mut history = History::new();
+        assert_eq!(history.undo(0), None);
+        history.record(0);
+        history.record(1);
+        assert_eq!(history.undo(2), Some(1));
+        assert_eq!(history.undo(1), Some(0));
+        assert_eq!(history.undo(0), None);
+        assert_eq!(history.redo(0), Some(1));
+        assert_eq!(history.redo(1), Some(2));
+        assert_eq!(history.redo(2), None);
+
+        assert_eq!(history.undo(2), Some(1));
+        history.record(1);
+        assert_eq!(history.redo(3), None);
+        assert_eq!(history.undo(3), Some(1));
+        assert_eq!(history.undo(1), Some(0));
+        assert_eq!(history.redo(0), Some(1));
+
+        history.clear();
+        assert_eq!(history.undo(0), None);
+        assert_eq!(history.redo(0), None);
+    }
+}
@@ -1,78 +1,293 @@
+mod balance;
+mod history;
+mod normalize;
 mod parser;
+mod report;
+mod unfinished;
+
+pub use parser::is_operation;
 
 use std::borrow::Cow::{self, Borrowed, Owned};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, IsTerminal, Write};
 
-use crate::ledger::{get_accounts, get_commodities, write_transaction};
-use crate::transaction::Transaction;
+use crate::commodity::CommodityFormats;
+use crate::config::{Config, ExpectedSign, DEFAULT_PRECISION};
+use crate::ledger::{
+    amount_column, check_balanced, commodity_formats, default_account, find_transactions,
+    get_accounts, get_balance, get_commodities, get_payees, output_date_format, plan_transaction,
+    remove_transaction, transactions_on, write_transaction, write_transactions,
+};
+use crate::transaction::{Amount, Transaction, TransactionDisplay};
+use history::History;
+use report::{ErrorKind, ErrorReporter};
 
-use rustyline::completion::{extract_word, Completer};
+use rustyline::completion::Completer;
 use rustyline::config::OutputStreamType;
 use rustyline::error::ReadlineError;
 use rustyline::highlight::{Highlighter, MatchingBracketHighlighter};
 use rustyline::hint::{Hinter, HistoryHinter};
-use rustyline::{Cmd, CompletionType, Config, Context, EditMode, Editor, KeyPress};
+use rustyline::{Cmd, CompletionType, Context, EditMode, Editor, KeyPress, Movement};
 use rustyline_derive::{Helper, Validator};
 
 use anyhow::Result;
+use chrono::{Local, NaiveDate};
+
+const HEADER_HELP: &str = "\
+Start a transaction by entering its header:
+    <Date> <Description>        2020-03-02 Lidl
+The date can also be `today` (or `.`), `yesterday` or an offset like `-3`.
+With --sticky-date, the date can be left out to reuse the last saved one.
+`bal [<Pattern>...]` prints the balances of accounts matching the patterns.
+`save` writes the transactions queued with --queue.
+`file [<Path>]` switches to another ledger file or prints the active one.
+`load <Date> <Description>` continues with a copy of a journal's transaction.
+`alias <Alias> = <Account>` lets the account be entered as the alias, `alias`
+lists the aliases.
+`reopen`, right after saving a transaction, removes it from the journal and
+continues editing it.";
+
+const CHANGE_HELP: &str = "\
+Available commands:
+    a <Account> <Currency> <Amount>
+        Adds the amount to the account, e.g. `a Expenses:Food € 5`
+    a <Account> <Currency> <Amount> ~ <Currency> <Amount>
+        Adds the amount at the total cost after `~`,
+        e.g. `a Expenses:Food CZK 250 ~ € 10`
+    s <Account> <Account> <Currency> <Amount>... [<Share>:<Share>]
+        Splits the amounts in half, or by the shares, between the accounts,
+        e.g. `s Expenses:Food Debts:Roomie € 10 USD 5` or `... € 30 2:1`
+    s <Account> <Account>:<Ratio>... <Currency> <Amount>...
+        Splits the amounts by the ratios, the first account gets the rest,
+        e.g. `s Expenses:Food Debts:Pete:0.3 Debts:Anna:0.2 € 100`
+    t <Account> <Account> <Currency> <Amount>
+        Transfers the amount from the first account to the second,
+        e.g. `t Assets:Checking Assets:Cash € 50`
+    lend <Account> <Account> <Currency> <Amount>
+        Lends the amount from the second account to the first,
+        e.g. `lend Debts:Pete Assets:Cash € 50`
+    split <Account> <Name>... <Currency> <Amount>
+        Splits the amount evenly between the account and the named people,
+        e.g. `split Expenses:Food anna bob € 60`
+    tip <Currency> <Total> <Account> <Subtotal>...
+        Distributes the total in proportion to the subtotals,
+        e.g. `tip € 72 Debts:Anna 30 Expenses:Food 30`
+    pct <Currency> <Total> <Account> <Percentage>... [<Account>]
+        Distributes the total by the percentages, the last account can be
+        left without one to take the rest, e.g. `pct € 90 Expenses 50 Debts:Anna`
+    f <Account> [<Currency>]
+        Balances the transaction (or just the currency) using the account,
+        e.g. `f Assets:Checking`. The account can be left out when the
+        journal declares one with a bucket directive.
+    assert <Account> <Currency> <Amount>
+        Asserts the balance of the account after the transaction,
+        e.g. `assert Assets:Cash € 100`
+    ff
+        Balances the transaction using the --finalize-account and saves it.
+        Meta-F fills in this command.
+    desc <Description>
+        Changes the description of the transaction
+    date <Date>
+        Changes the date of the transaction
+    tag <Name> <Value>
+        Tags the transaction, e.g. `tag trip Japan2024`
+    bal [<Pattern>...]
+        Prints the balances of accounts matching the patterns
+    recent
+        Lists the accounts used so far, which can then be entered by their
+        number, e.g. `a @2 € 5`
+    u, r
+        Undoes the last change, redoes the last undone one
+    clear
+        Removes all postings, keeping the date, description and tags
+    help, ?
+        Prints this help
+A `-` after a, s, split or t negates the amount, e.g. `a- Income:Salary € 50`.
+The currency can be left out to reuse the last entered one (except for split).
+An empty line finalizes the transaction and saves it into the ledger file.";
 
 #[derive(Helper, Validator)]
 struct TUIHelper {
     path_to_ledger: String,
+    config: Config,
     hinter: HistoryHinter,
     highlighter: MatchingBracketHighlighter,
     colored_prompt: String,
+    /// Whether a header is being entered, rather than a change.
+    header: bool,
 }
 
 impl TUIHelper {
-    fn new(path_to_ledger: String) -> TUIHelper {
+    fn new(path_to_ledger: String, config: Config) -> TUIHelper {
         TUIHelper {
             path_to_ledger,
+            config,
             highlighter: MatchingBracketHighlighter::new(),
             hinter: HistoryHinter {},
             colored_prompt: "".to_owned(),
+            header: true,
         }
     }
 
-    fn expected_token(&self, line: &str, word_start: usize) -> Option<parser::TokenType> {
-        // The only supported separator is a space, ASCII 32.
-        let words: Vec<&str> = line.split_ascii_whitespace().collect();
-        let mut p = parser::Parser::new();
-        let mut parsed_characters = 0usize;
-        for word in words {
-            parsed_characters += word.len() + 1;
-            if parsed_characters > word_start {
+    /// Returns the parser state at the cursor, after parsing the words before
+    /// the one the cursor is in.
+    fn parse_before<'a>(&'a self, line: &'a str, pos: usize) -> Option<parser::Parser<'a>> {
+        let mut p = parser::Parser::new()
+            .amount_first(self.config.amount_first)
+            .keywords(&self.config.keywords)
+            .account_aliases(&self.config.account_aliases);
+        for (start, word) in parser::tokenize(line) {
+            if start + word.len() >= pos {
                 break;
             }
             if p.parse_word(word).is_err() {
                 return None;
             }
         }
-        Some(p.next)
+        Some(p)
+    }
+
+    /// Returns where the completed word starts and what it can be completed to.
+    fn candidates(&self, line: &str, pos: usize) -> Result<(usize, Vec<String>), ReadlineError> {
+        if self.header {
+            return match description_start(line, pos) {
+                Some(start) => Ok((
+                    start,
+                    get_payees(&self.path_to_ledger, &self.config, &line[start..pos])?,
+                )),
+                None => Ok((0, vec![])),
+            };
+        }
+        let (word_start, word_to_complete) = parser::word_at(line, pos);
+        let parsed = self.parse_before(line, pos);
+        match parsed.as_ref().map(|p| p.next) {
+            Some(parser::TokenType::Account) => {
+                let candidates = matching_aliases(word_to_complete, &self.config.account_aliases)
+                    .into_iter()
+                    .chain(account_candidates(
+                        word_to_complete,
+                        get_accounts(&self.path_to_ledger, &self.config, "")?,
+                    ))
+                    .collect();
+                let counterparty = parsed.is_some_and(|p| p.expects_counterparty());
+                Ok((
+                    word_start,
+                    if counterparty {
+                        debts_first(&self.config.debt_prefix, candidates)
+                    } else {
+                        candidates
+                    },
+                ))
+            }
+            Some(parser::TokenType::Currency) | Some(parser::TokenType::Name) => Ok((
+                word_start,
+                get_commodities(&self.path_to_ledger, &self.config, word_to_complete)?,
+            )),
+            _ => Ok((0, vec![])),
+        }
     }
 }
 
 impl Completer for TUIHelper {
     type Candidate = String;
 
+    /// Errors would end the editor, so they are only printed and nothing is
+    /// offered, like when ledger can't parse the journal.
     fn complete(
         &self,
         line: &str,
         pos: usize,
         _: &Context<'_>,
     ) -> Result<(usize, Vec<String>), ReadlineError> {
-        let (word_start, word_to_complete) = extract_word(line, pos, None, &[32u8][..]);
-        match self.expected_token(line, pos) {
-            Some(parser::TokenType::Account) => Ok((
-                word_start,
-                get_accounts(&self.path_to_ledger, word_to_complete)?,
-            )),
-            Some(parser::TokenType::Currency) => Ok((
-                word_start,
-                get_commodities(&self.path_to_ledger, word_to_complete)?,
-            )),
-            _ => Ok((0, vec![])),
+        match self.candidates(line, pos) {
+            Ok(candidates) => Ok(candidates),
+            Err(err) => {
+                eprintln!("\nWarning: can't complete, {}", err);
+                Ok((0, vec![]))
+            }
+        }
+    }
+}
+
+/// Returns where the description of the header starts when the cursor is in
+/// it, past the date. Descriptions can contain spaces, so they are completed
+/// as a whole.
+fn description_start(line: &str, pos: usize) -> Option<usize> {
+    let tokens = parser::tokenize(line);
+    let (date_start, date) = tokens.first()?;
+    if date_start + date.len() >= pos {
+        return None;
+    }
+    Some(match tokens.get(1) {
+        Some((start, _)) if *start <= pos => *start,
+        _ => pos,
+    })
+}
+
+/// Returns the accounts offered for the typed text. Once a parent account is
+/// typed with its trailing colon, only its subaccounts are offered, and none
+/// when it has no subaccounts yet, so a new one can be typed freely.
+fn account_candidates(typed: &str, accounts: Vec<String>) -> Vec<String> {
+    let typed = normalize::nfc(typed);
+    let matching = matching_accounts(&typed, accounts);
+    let candidates = if typed.ends_with(':') {
+        let parent = typed.to_lowercase();
+        matching
+            .into_iter()
+            .filter(|account| account.to_lowercase().starts_with(&parent))
+            .collect()
+    } else {
+        matching
+    };
+    account_segments(&typed, candidates)
+}
+
+/// Returns the account aliases starting with the typed text, sorted.
+fn matching_aliases(typed: &str, aliases: &HashMap<String, String>) -> Vec<String> {
+    let mut matching: Vec<String> = aliases
+        .keys()
+        .filter(|alias| alias.starts_with(typed))
+        .cloned()
+        .collect();
+    matching.sort();
+    matching
+}
+
+/// Moves the accounts under the debt prefix to the front, keeping the order
+/// of the accounts otherwise.
+fn debts_first(debt_prefix: &str, accounts: Vec<String>) -> Vec<String> {
+    let (debts, others): (Vec<String>, Vec<String>) = accounts
+        .into_iter()
+        .partition(|account| account.starts_with(debt_prefix));
+    debts.into_iter().chain(others).collect()
+}
+
+/// Returns the accounts containing the typed text, ignoring case and
+/// differences in Unicode composition.
+fn matching_accounts(typed: &str, accounts: Vec<String>) -> Vec<String> {
+    let typed = normalize::nfc(typed).to_lowercase();
+    accounts
+        .iter()
+        .map(|account| normalize::nfc(account))
+        .filter(|account| account.to_lowercase().contains(&typed))
+        .collect()
+}
+
+/// Shortens accounts starting with the typed prefix to the next segment, like
+/// a shell completes paths, so deep hierarchies can be completed step by step.
+fn account_segments(typed: &str, accounts: Vec<String>) -> Vec<String> {
+    let mut segments: Vec<String> = Vec::new();
+    for account in accounts {
+        let segment = match account.strip_prefix(typed).and_then(|rest| rest.find(':')) {
+            Some(colon) => account[..typed.len() + colon + 1].to_owned(),
+            None => account,
+        };
+        if !segments.contains(&segment) {
+            segments.push(segment);
         }
     }
+    segments
 }
 
 impl Hinter for TUIHelper {
@@ -109,50 +324,136 @@ impl Highlighter for TUIHelper {
 
 pub struct TUIController {
     path_to_ledger: String,
+    config: Config,
     current_tx: Option<Transaction>,
+    last_currency: Option<String>,
+    write_failed: bool,
+    /// Accounts and currencies of the current transaction which were changed
+    /// in both directions.
+    opposing_changes: Vec<(String, String)>,
+    /// Earlier and undone states of the current transaction.
+    history: History<Transaction>,
+    /// Account declared by the journal's `bucket` directive, which `f`
+    /// finalizes into when no account is given.
+    default_account: Option<String>,
+    /// Finished transactions waiting to be written by the `save` command.
+    queue: Vec<Transaction>,
+    /// The transaction saved last, its text and the line it was written at.
+    last_written: Option<(Transaction, String, usize)>,
+    /// Accounts used in the session, in the order of their first use, so
+    /// that their numbers don't change.
+    recent_accounts: Vec<String>,
+    /// Date of the transaction saved last, with `--sticky-date`.
+    sticky_date: Option<NaiveDate>,
+    reporter: ErrorReporter,
     editor: rustyline::Editor<TUIHelper>,
+    /// Whether the history of entered lines was saved since the last one was
+    /// entered.
+    history_saved: bool,
+    /// Reads the entered lines instead of the editor when stdin or stdout
+    /// isn't a terminal.
+    plain_input: Option<LineReader>,
 }
 
+/// Reads a line into the buffer like `BufRead::read_line`.
+type LineReader = Box<dyn FnMut(&mut String) -> io::Result<usize>>;
+
 impl TUIController {
-    pub fn new(path_to_ledger: String) -> TUIController {
-        let editor_config = Config::builder()
+    pub fn new(path_to_ledger: String, config: Config) -> TUIController {
+        let editor_config = rustyline::Config::builder()
             .history_ignore_space(true)
             .completion_type(CompletionType::List)
-            .edit_mode(EditMode::Emacs)
+            .edit_mode(if config.vi_mode {
+                EditMode::Vi
+            } else {
+                EditMode::Emacs
+            })
             .output_stream(OutputStreamType::Stdout)
             .build();
         let mut editor = Editor::with_config(editor_config);
-        editor.set_helper(Some(TUIHelper::new(path_to_ledger.clone())));
+        editor.set_helper(Some(TUIHelper::new(path_to_ledger.clone(), config.clone())));
         editor.bind_sequence(KeyPress::Meta('N'), Cmd::HistorySearchForward);
         editor.bind_sequence(KeyPress::Meta('P'), Cmd::HistorySearchBackward);
-        if editor.load_history("history.txt").is_err() {
+        // Key bindings can't accept the line, so Meta-F only fills in the
+        // command and Enter has to confirm it.
+        editor.bind_sequence(
+            KeyPress::Meta('F'),
+            Cmd::Replace(Movement::WholeLine, Some("ff".to_owned())),
+        );
+        // Piped input is read line by line without the editor, so there are
+        // no prompts, highlighting or history to mix into the output.
+        let plain_input: Option<LineReader> =
+            if io::stdin().is_terminal() && io::stdout().is_terminal() {
+                None
+            } else {
+                Some(Box::new(|line| io::stdin().read_line(line)))
+            };
+        let plain = plain_input.is_some();
+        if !config.no_history && !plain && editor.load_history(&config.history_file).is_err() {
             println!("No previous history.");
         }
+        let mut current_tx = if plain {
+            None
+        } else {
+            unfinished::take(&path_to_ledger).filter(|tx| {
+                print!("{}", tx);
+                confirm("Resume this unfinished transaction? [y/N] ")
+            })
+        };
+        if config.quick && current_tx.is_none() {
+            current_tx = Some(Transaction::new(
+                Local::today().naive_local(),
+                String::new(),
+            ));
+        }
+        let default_account = default_account(&path_to_ledger).unwrap_or(None);
         TUIController {
-            current_tx: None,
+            current_tx,
+            default_account,
+            last_currency: config.default_currency.clone(),
+            write_failed: false,
+            opposing_changes: Vec::new(),
+            history: History::new(),
+            history_saved: true,
+            plain_input,
+            queue: Vec::new(),
+            last_written: None,
+            sticky_date: None,
+            recent_accounts: Vec::new(),
+            reporter: ErrorReporter::new(config.machine_errors),
             editor,
             path_to_ledger,
+            config,
         }
     }
 
+    /// Runs the editor until the user quits. Returns the error which ended
+    /// it, if there was one.
     pub fn run(&mut self) -> rustyline::Result<()> {
+        let mut result = Ok(());
         loop {
             let p = if self.current_tx.is_none() {
                 "header> ".to_owned()
             } else {
                 "change> ".to_owned()
             };
-            self.editor.helper_mut().expect("No helper").colored_prompt =
-                format!("\x1b[1;32m{}\x1b[0m", p);
-            let line = self.editor.readline(&p);
+            let helper = self.editor.helper_mut().expect("No helper");
+            helper.colored_prompt = format!("\x1b[1;32m{}\x1b[0m", p);
+            helper.header = self.current_tx.is_none();
+            let line = self.read_line(&p);
             match line {
                 Ok(line) => {
-                    self.editor.add_history_entry(line.clone());
-                    let trimmed = line.trim();
+                    self.reporter.next_line();
+                    if !self.config.no_history && self.plain_input.is_none() {
+                        self.editor.add_history_entry(line.clone());
+                        self.history_saved = false;
+                    }
+                    let normalized = normalize::nfc(line.trim());
+                    let trimmed = normalized.as_str();
                     if self.current_tx.is_none() {
-                        self.parse_header(&trimmed);
+                        self.parse_header(trimmed);
                     } else {
-                        self.parse_change(&trimmed);
+                        self.parse_change(trimmed);
                     }
                 }
                 Err(ReadlineError::Interrupted) => {
@@ -162,45 +463,954 @@ impl TUIController {
                     break;
                 }
                 Err(err) => {
-                    println!("Error: {:?}", err);
+                    result = Err(err);
                     break;
                 }
             }
         }
-        self.editor.save_history("history.txt")
+        if !self.queue.is_empty() {
+            self.save_queue();
+            for tx in &self.queue {
+                print!("Unsaved transaction:\n{}", tx);
+            }
+        }
+        // A transaction started by --quick isn't worth resuming until
+        // something is entered.
+        let entered = |tx: &&Transaction| !tx.description.is_empty() || !tx.changes.is_empty();
+        if let Some(tx) = self.current_tx.as_ref().filter(entered) {
+            if let Err(err) = unfinished::save(&self.path_to_ledger, tx) {
+                self.reporter.report(
+                    ErrorKind::Save,
+                    format!("Error when storing the unfinished transaction: {}", err),
+                );
+            }
+        }
+        self.save_history()?;
+        result
+    }
+
+    /// Reads the next line with the editor, or without the prompt from the
+    /// plain input.
+    fn read_line(&mut self, prompt: &str) -> rustyline::Result<String> {
+        let read = match self.plain_input.as_mut() {
+            Some(read) => read,
+            None => return self.editor.readline(prompt),
+        };
+        let mut line = String::new();
+        if read(&mut line)? == 0 {
+            return Err(ReadlineError::Eof);
+        }
+        let len = line.trim_end_matches(&['\r', '\n'][..]).len();
+        line.truncate(len);
+        Ok(line)
+    }
+
+    fn save_history(&mut self) -> rustyline::Result<()> {
+        if self.config.no_history || self.plain_input.is_some() {
+            return Ok(());
+        }
+        self.editor.save_history(&self.config.history_file)?;
+        self.history_saved = true;
+        Ok(())
+    }
+
+    /// The file transactions are written into, the active journal unless
+    /// another output file is configured.
+    fn output_path(&self) -> &str {
+        self.config
+            .output_file
+            .as_deref()
+            .unwrap_or(&self.path_to_ledger)
+    }
+
+    /// Whether saving any of the transactions failed.
+    pub fn write_failed(&self) -> bool {
+        self.write_failed
     }
 
     fn parse_header(&mut self, line: &str) {
-        match parser::parse_transaction_header(line) {
-            Ok(transaction) => self.current_tx = Some(transaction),
-            Err(err) => println!("{}", err),
+        let last_written = self.last_written.take();
+        if line == "reopen" {
+            match last_written {
+                Some((tx, text, tx_line)) => self.reopen(tx, &text, tx_line),
+                None => self.reporter.report(
+                    ErrorKind::Parse,
+                    "Only a transaction which was just saved can be reopened",
+                ),
+            }
+            return;
+        }
+        if line == "save" {
+            self.save_queue();
+            return;
+        }
+        if line == "help" || line == "?" {
+            println!("{}", HEADER_HELP);
+            return;
+        }
+        let words = parser::words(line);
+        if words.first() == Some(&"bal") {
+            self.print_account_balances(&words[1..]);
+            return;
+        }
+        if words.first() == Some(&"file") {
+            match line["file".len()..].trim() {
+                "" => println!("Active ledger file: {}", self.path_to_ledger),
+                path => self.switch_file(path),
+            }
+            return;
+        }
+        if words.first() == Some(&"alias") {
+            self.add_alias(line["alias".len()..].trim());
+            return;
+        }
+        if words.first() == Some(&"load") {
+            self.load_transaction(line["load".len()..].trim());
+            return;
+        }
+        match parser::parse_transaction_header(
+            line,
+            &self.config.input_date_format,
+            self.sticky_date,
+        ) {
+            Ok(transaction) => self.start_transaction(transaction),
+            Err(err) => self.reporter.report(ErrorKind::Parse, err),
         };
     }
 
     fn parse_change(&mut self, line: &str) {
-        if line == "" {
-            let tx_ref = self.current_tx.as_ref().unwrap();
+        if line.is_empty() {
+            self.save_transaction();
+            return;
+        }
+        let words = parser::words(line);
+        match words[0] {
+            "desc" => {
+                self.history.record(self.current_tx.clone().unwrap());
+                self.current_tx.as_mut().unwrap().description =
+                    parser::parse_description(&words[1..]);
+                return;
+            }
+            "u" | "r" => {
+                self.undo(words[0] == "r");
+                return;
+            }
+            "clear" => {
+                self.clear_changes();
+                return;
+            }
+            "help" | "?" => {
+                println!("{}", CHANGE_HELP);
+                return;
+            }
+            // `ff` is applied like `f` with the preferred account, then the
+            // transaction is saved.
+            "ff" => match &self.config.finalize_account {
+                Some(_) if self.current_tx.as_ref().unwrap().is_balanced(None) => {
+                    self.save_transaction();
+                    return;
+                }
+                Some(_) => {}
+                None => {
+                    self.reporter.report(
+                        ErrorKind::Parse,
+                        "No finalize account configured, use --finalize-account",
+                    );
+                    return;
+                }
+            },
+            "bal" => {
+                self.print_account_balances(&words[1..]);
+                return;
+            }
+            "recent" => {
+                for (i, account) in self.recent_accounts.iter().enumerate() {
+                    println!("@{}\t{}", i + 1, account);
+                }
+                return;
+            }
+            "tag" => {
+                match parser::parse_tag(&words[1..]) {
+                    Ok((key, value)) => {
+                        self.history.record(self.current_tx.clone().unwrap());
+                        self.current_tx.as_mut().unwrap().add_tag(&key, &value)
+                    }
+                    Err(err) => self.reporter.report(ErrorKind::Parse, err),
+                }
+                return;
+            }
+            "date" => {
+                match parser::parse_header_date(&words[1..], &self.config.input_date_format) {
+                    Ok(date) => {
+                        self.history.record(self.current_tx.clone().unwrap());
+                        self.current_tx.as_mut().unwrap().date = date
+                    }
+                    Err(err) => self.reporter.report(ErrorKind::Parse, err),
+                }
+                return;
+            }
+            _ => {}
+        }
+        let line = match parser::expand_recent(line, &self.recent_accounts) {
+            Ok(line) => line,
+            Err(err) => {
+                self.reporter.report(ErrorKind::Parse, err);
+                return;
+            }
+        };
+        let finalize_and_save = words[0] == "ff";
+        let validated = match &self.config.finalize_account {
+            Some(account) if finalize_and_save => Ok(parser::Operation::Finalize(account, None)),
+            _ => parser::Parser::with_default_currency(self.last_currency.as_deref())
+                .amount_first(self.config.amount_first)
+                .default_account(self.default_account.as_deref())
+                .commodity_aliases(&self.config.commodity_aliases)
+                .keywords(&self.config.keywords)
+                .account_aliases(&self.config.account_aliases)
+                .pad_amounts(
+                    self.config
+                        .pad_amounts
+                        .then_some((&self.config.precisions, DEFAULT_PRECISION)),
+                )
+                .validate_change(&line),
+        };
+        match validated {
+            Ok(op) => {
+                for amount in op.amounts() {
+                    if !self.confirm_if_large(amount) {
+                        println!("Change not applied");
+                        return;
+                    }
+                }
+                let currency = op.currency().map(|c| c.to_owned());
+                let tx = self.current_tx.as_mut().unwrap();
+                if let parser::Operation::Finalize(_, finalized_currency) = op {
+                    if tx.is_balanced(finalized_currency) {
+                        println!("The transaction is already balanced");
+                        return;
+                    }
+                }
+                if self.config.tag_counterparties {
+                    for name in op.counterparties(&self.config.debt_prefix) {
+                        tx.add_unique_tag("counterparty", &name);
+                    }
+                }
+                for account in op.accounts() {
+                    if !self.recent_accounts.iter().any(|recent| recent == account) {
+                        self.recent_accounts.push(account.to_owned());
+                    }
+                }
+                let earlier = tx.clone();
+                self.history.record(earlier.clone());
+                let config = &self.config;
+                op.add_to_transation(tx, &config.debt_prefix, |currency| {
+                    config.precision(currency)
+                });
+                for (account, change) in tx.changes_since(&earlier) {
+                    let unexpected = match config.expected_sign(&account) {
+                        Some(ExpectedSign::Positive) => change.1.is_sign_negative(),
+                        Some(ExpectedSign::Negative) => change.1.is_sign_positive(),
+                        None => false,
+                    };
+                    if unexpected {
+                        println!(
+                            "Warning: {} changed by {}, against its usual sign",
+                            account, change
+                        );
+                    }
+                }
+                for opposing in tx.opposing_changes(&earlier) {
+                    if !self.opposing_changes.contains(&opposing) {
+                        self.opposing_changes.push(opposing);
+                    }
+                }
+                if currency.is_some() {
+                    self.last_currency = currency;
+                }
+                if finalize_and_save {
+                    self.save_transaction();
+                } else if !self.config.quiet {
+                    self.print_balance();
+                }
+            }
+            Err(err) => self.reporter.report(ErrorKind::Parse, err),
+        }
+    }
+
+    fn save_transaction(&mut self) {
+        if self.config.quick && !self.ask_for_description() {
+            println!("Transaction not saved");
+            return;
+        }
+        let tx_ref = self.current_tx.as_ref().unwrap();
+        // Refused transactions stay open, so they can be balanced.
+        if self.config.strict_balance {
+            if let Err(err) = check_balanced(tx_ref) {
+                self.reporter.report(ErrorKind::Save, err);
+                return;
+            }
+        }
+        if self.config.check_duplicates && !self.confirm_if_duplicate(tx_ref) {
+            println!("Transaction not saved");
+            return;
+        }
+        if self.config.check_opposing_changes && !self.confirm_if_opposing() {
+            println!("Transaction not saved");
+            return;
+        }
+        if self.config.sticky_date {
+            self.sticky_date = Some(tx_ref.date);
+        }
+        if self.config.queue_transactions && !self.config.read_only && !self.config.plan_json {
             print!("{}", tx_ref);
-            if let Err(err) = write_transaction(&self.path_to_ledger, tx_ref) {
-                println!("Error when saving the transaction: {}", err);
+            self.queue.push(self.current_tx.take().unwrap());
+            println!(
+                "Transaction queued, {} waiting to be saved with `save`",
+                self.queue.len()
+            );
+            return;
+        }
+        let date_format = match output_date_format(&self.path_to_ledger, &self.config) {
+            Ok(date_format) => date_format,
+            Err(err) => {
+                self.reporter.report(
+                    ErrorKind::Ledger,
+                    format!("Error when reading the ledger file: {}", err),
+                );
+                return;
+            }
+        };
+        let formats = commodity_formats(&self.path_to_ledger, &self.config)
+            .unwrap_or_else(|_| self.config.commodity_formats.clone());
+        let amount_column = amount_column(&self.path_to_ledger, &self.config).unwrap_or(None);
+        let tx_display =
+            configured_display(tx_ref, &date_format, &formats, amount_column, &self.config);
+        print!("{}", tx_display);
+        if self.config.read_only {
+            println!("Transaction not written, splitter is read-only");
+            self.current_tx = None;
+            return;
+        }
+        if self.config.plan_json {
+            match plan_transaction(self.output_path(), &self.config, &tx_display) {
+                Ok(plan) => println!("{}", plan),
+                Err(err) => self.reporter.report(
+                    ErrorKind::Ledger,
+                    format!("Error when planning the insertion: {}", err),
+                ),
             }
             self.current_tx = None;
             return;
         }
-        let mut p = parser::Parser::new();
-        for word in line.split_ascii_whitespace() {
-            let result = p.parse_word(word);
-            if let Err(err) = result {
-                println!("{}", err);
-                continue;
+        match write_transaction(self.output_path(), &self.config, &tx_display, confirm_diff) {
+            Ok(line) => {
+                println!(
+                    "Transaction inserted at line {} of {}",
+                    line,
+                    self.output_path()
+                );
+                self.last_written = Some((tx_ref.clone(), tx_display.to_string(), line));
+            }
+            Err(ReadlineError::Interrupted) => {
+                println!("Transaction not written");
+                return;
+            }
+            Err(err) => {
+                self.reporter.report(
+                    ErrorKind::Save,
+                    format!("Error when saving the transaction: {}", err),
+                );
+                self.write_failed = true;
+            }
+        }
+        self.current_tx = None;
+    }
+
+    /// Asks for the description of the transaction when it's blank, like when
+    /// it was started by --quick. Returns whether it has a description.
+    fn ask_for_description(&mut self) -> bool {
+        if !self
+            .current_tx
+            .as_ref()
+            .unwrap()
+            .description
+            .trim()
+            .is_empty()
+        {
+            return true;
+        }
+        match self.read_line("description> ") {
+            Ok(line) if !line.trim().is_empty() => {
+                let tx = self.current_tx.as_mut().unwrap();
+                tx.description = parser::parse_description(&parser::words(&line));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Writes all the queued transactions into the ledger file.
+    fn save_queue(&mut self) {
+        if self.queue.is_empty() {
+            println!("No transactions are queued");
+            return;
+        }
+        let date_format = match output_date_format(&self.path_to_ledger, &self.config) {
+            Ok(date_format) => date_format,
+            Err(err) => {
+                self.reporter.report(
+                    ErrorKind::Ledger,
+                    format!("Error when reading the ledger file: {}", err),
+                );
+                self.write_failed = true;
+                return;
+            }
+        };
+        let formats = commodity_formats(&self.path_to_ledger, &self.config)
+            .unwrap_or_else(|_| self.config.commodity_formats.clone());
+        let amount_column = amount_column(&self.path_to_ledger, &self.config).unwrap_or(None);
+        let displays: Vec<TransactionDisplay> = self
+            .queue
+            .iter()
+            .map(|tx| configured_display(tx, &date_format, &formats, amount_column, &self.config))
+            .collect();
+        match write_transactions(self.output_path(), &self.config, &displays, confirm_diff) {
+            Ok(lines) => {
+                for (tx, line) in self.queue.iter().zip(lines) {
+                    println!(
+                        "{} {} inserted at line {} of {}",
+                        tx.date,
+                        tx.description,
+                        line,
+                        self.output_path()
+                    );
+                }
+                self.queue.clear();
+            }
+            Err(ReadlineError::Interrupted) => println!("Transactions not written"),
+            Err(err) => {
+                self.reporter.report(
+                    ErrorKind::Save,
+                    format!("Error when saving the transactions: {}", err),
+                );
+                self.write_failed = true;
+            }
+        }
+    }
+
+    /// Makes the file the one transactions are added to. Queued transactions
+    /// are written into the previous file first.
+    fn switch_file(&mut self, path: &str) {
+        match File::open(path).and_then(|file| file.metadata()) {
+            Ok(metadata) if metadata.is_file() => {}
+            Ok(_) => {
+                self.reporter
+                    .report(ErrorKind::Ledger, format!("{} is not a file", path));
+                return;
+            }
+            Err(err) => {
+                self.reporter
+                    .report(ErrorKind::Ledger, format!("Can't read {}: {}", path, err));
+                return;
+            }
+        }
+        if !self.queue.is_empty() {
+            self.save_queue();
+            if !self.queue.is_empty() {
+                println!(
+                    "Queued transactions couldn't be saved, staying with {}",
+                    self.path_to_ledger
+                );
+                return;
+            }
+        }
+        self.path_to_ledger = path.to_owned();
+        self.editor.helper_mut().expect("No helper").path_to_ledger = path.to_owned();
+        self.default_account = default_account(path).unwrap_or(None);
+        println!("Active ledger file: {}", self.path_to_ledger);
+    }
+
+    /// Adds an account alias given like `g = Expenses:Food:Groceries`, or
+    /// lists the aliases when none is given.
+    fn add_alias(&mut self, alias: &str) {
+        if alias.is_empty() {
+            let mut aliases: Vec<_> = self.config.account_aliases.iter().collect();
+            aliases.sort();
+            for (alias, account) in aliases {
+                println!("{} = {}", alias, account);
+            }
+            return;
+        }
+        match alias.split_once('=') {
+            Some((alias, account)) if !alias.trim().is_empty() && !account.trim().is_empty() => {
+                let (alias, account) = (alias.trim().to_owned(), account.trim().to_owned());
+                let helper = self.editor.helper_mut().expect("No helper");
+                helper
+                    .config
+                    .account_aliases
+                    .insert(alias.clone(), account.clone());
+                self.config.account_aliases.insert(alias, account);
             }
+            _ => self.reporter.report(
+                ErrorKind::Parse,
+                format!("Invalid alias {}, expecting <Alias> = <Account>", alias),
+            ),
         }
-        if p.next != parser::TokenType::EOL {
-            println!("Invalid change command, expecting {:?}", p.next);
+    }
+
+    /// Continues with editing the transaction, which has nothing to undo yet.
+    fn start_transaction(&mut self, tx: Transaction) {
+        self.current_tx = Some(tx);
+        self.opposing_changes.clear();
+        self.history.clear();
+    }
+
+    /// Starts the transaction over with the same header and tags, but none of
+    /// its postings and nothing to undo.
+    fn clear_changes(&mut self) {
+        let tx = self.current_tx.as_ref().unwrap();
+        let mut cleared = Transaction::new(tx.date, tx.description.clone());
+        cleared.tags = tx.tags.clone();
+        println!(
+            "Changes cleared, continuing with {} {}",
+            cleared.date, cleared.description
+        );
+        self.start_transaction(cleared);
+    }
+
+    /// Goes back to the state of the transaction before the last change, or
+    /// forward to the one before the last undo.
+    fn undo(&mut self, redo: bool) {
+        let current = self.current_tx.take().unwrap();
+        let restored = if redo {
+            self.history.redo(current.clone())
         } else {
-            p.operation()
-                .unwrap()
-                .add_to_transation(&mut self.current_tx.as_mut().unwrap());
+            self.history.undo(current.clone())
+        };
+        match restored {
+            Some(tx) => {
+                self.current_tx = Some(tx);
+                if !self.config.quiet {
+                    self.print_balance();
+                }
+            }
+            None => {
+                self.current_tx = Some(current);
+                println!("Nothing to {}", if redo { "redo" } else { "undo" });
+            }
         }
     }
+
+    /// Starts a new transaction as a copy of one in the journal, given its
+    /// header. The original stays in the journal.
+    fn load_transaction(&mut self, header: &str) {
+        let wanted = match parser::parse_transaction_header(
+            header,
+            &self.config.input_date_format,
+            self.sticky_date,
+        ) {
+            Ok(wanted) => wanted,
+            Err(err) => {
+                self.reporter.report(ErrorKind::Parse, err);
+                return;
+            }
+        };
+        match find_transactions(
+            &self.path_to_ledger,
+            &self.config,
+            wanted.date,
+            &wanted.description,
+        ) {
+            Ok(found) => match found.into_iter().next() {
+                Some(tx) => {
+                    print!("{}", tx);
+                    self.start_transaction(tx);
+                }
+                None => self.reporter.report(
+                    ErrorKind::Parse,
+                    format!(
+                        "No transaction {} {} in the journal",
+                        wanted.date, wanted.description
+                    ),
+                ),
+            },
+            Err(err) => self.reporter.report(
+                ErrorKind::Ledger,
+                format!("Error when reading the transaction: {}", err),
+            ),
+        }
+    }
+
+    fn reopen(&mut self, tx: Transaction, text: &str, tx_line: usize) {
+        match remove_transaction(self.output_path(), &self.config, text, tx_line) {
+            Ok(()) => {
+                print!("{}", tx);
+                self.start_transaction(tx);
+            }
+            Err(err) => self.reporter.report(
+                ErrorKind::Save,
+                format!("Error when removing the transaction: {}", err),
+            ),
+        }
+    }
+
+    /// Asks whether to add the amount if it's over the configured maximum.
+    /// Returns whether the amount should be added.
+    fn confirm_if_large(&self, amount: &Amount) -> bool {
+        match self.config.max_amount(&amount.0) {
+            Some(limit) if amount.1.abs() > limit => confirm(&format!(
+                "{} is over {}, are you sure? [y/N] ",
+                amount, limit
+            )),
+            _ => true,
+        }
+    }
+
+    /// Asks whether to save the transaction if any of its accounts was
+    /// changed in both directions. Returns whether it should be saved.
+    fn confirm_if_opposing(&self) -> bool {
+        if self.opposing_changes.is_empty() {
+            return true;
+        }
+        for (account, currency) in &self.opposing_changes {
+            println!(
+                "{} was both added to and subtracted from in {}",
+                account, currency
+            );
+        }
+        confirm("Save anyway? [y/N] ")
+    }
+
+    /// Asks whether to save the transaction if the journal already contains
+    /// the same one. Returns whether the transaction should be saved.
+    fn confirm_if_duplicate(&self, tx: &Transaction) -> bool {
+        match transactions_on(&self.path_to_ledger, &self.config, tx.date) {
+            Ok(existing) if existing.iter().any(|other| tx.is_duplicate_of(other)) => {
+                confirm("The journal already contains this transaction, save anyway? [y/N] ")
+            }
+            Ok(_) => true,
+            Err(err) => {
+                self.reporter.report(
+                    ErrorKind::Ledger,
+                    format!("Error when checking for duplicates: {}", err),
+                );
+                confirm("Save anyway? [y/N] ")
+            }
+        }
+    }
+
+    fn print_account_balances(&self, patterns: &[&str]) {
+        match get_balance(&self.path_to_ledger, &self.config, patterns) {
+            Ok(lines) => {
+                for line in lines {
+                    println!("{}", line);
+                }
+            }
+            Err(err) => self.reporter.report(
+                ErrorKind::Ledger,
+                format!("Error when getting the balance: {}", err),
+            ),
+        }
+    }
+
+    fn print_balance(&self) {
+        let unbalanced = self.current_tx.as_ref().unwrap().unbalanced();
+        if unbalanced.is_empty() {
+            println!("Balanced");
+            return;
+        }
+        println!("Balance:");
+        for row in balance::balance_table(
+            &unbalanced,
+            &self.config.commodity_formats,
+            &self.config.posting_indent,
+            &self.config.amount_separator,
+        ) {
+            println!("{}", row);
+        }
+    }
+}
+
+/// Saves the history when the controller is dropped without `run` saving it,
+/// like when a panic unwinds through it.
+impl Drop for TUIController {
+    fn drop(&mut self) {
+        if self.history_saved {
+            return;
+        }
+        if let Err(err) = self.save_history() {
+            eprintln!("Error when saving the history: {}", err);
+        }
+    }
+}
+
+/// Displays the transaction the way the configuration says it should be
+/// written into the journal.
+fn configured_display<'a>(
+    tx: &'a Transaction,
+    date_format: &'a str,
+    formats: &'a CommodityFormats,
+    amount_column: Option<usize>,
+    config: &'a Config,
+) -> TransactionDisplay<'a> {
+    let display = tx
+        .display(date_format)
+        .with_commodity_formats(formats)
+        .group_accounts(config.group_accounts)
+        .with_indentation(&config.posting_indent, &config.amount_separator)
+        .with_sign_display(config.sign_display)
+        .with_amount_column(amount_column);
+    if config.pad_amounts {
+        display.pad_amounts(&config.precisions, DEFAULT_PRECISION)
+    } else {
+        display
+    }
+}
+
+/// Shows the change of the journal and asks whether to write it.
+fn confirm_diff(diff: &str) -> bool {
+    print!("{}", diff);
+    confirm("Write this change? [y/N] ")
+}
+
+fn confirm(question: &str) -> bool {
+    let mut input = String::new();
+    print!("{}", question);
+    if io::stdout().flush().is_err() || io::stdin().read_line(&mut input).is_err() {
+        return false;
+    }
+    input.trim().eq_ignore_ascii_case("y")
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn header_description() {
+        assert_eq!(description_start("", 0), None);
+        assert_eq!(description_start("2020-03-02", 5), None);
+        assert_eq!(description_start("2020-03-02", 10), None);
+        assert_eq!(description_start("2020-03-02 ", 11), Some(11));
+        assert_eq!(description_start("2020-03-02 Lidl", 13), Some(11));
+        assert_eq!(description_start("today  Lidl Dejvice", 19), Some(7));
+        assert_eq!(description_start("today  Lidl", 6), Some(6));
+    }
+
+    #[test]
+    fn normalized_completion() {
+        let accounts = vec![
+            "Expenses:Cafe\u{301}".to_owned(),
+            "Expenses:Food".to_owned(),
+        ];
+        assert_eq!(
+            matching_accounts("café", accounts.clone()),
+            vec!["Expenses:Café"]
+        );
+        assert_eq!(
+            matching_accounts("Cafe\u{301}", accounts),
+            vec!["Expenses:Café"]
+        );
+    }
+
+    #[test]
+    fn segmented_completion() {
+        let accounts: Vec<String> = [
+            "Expenses",
+            "Expenses:Food",
+            "Expenses:Food:Groceries",
+            "Expenses:Food:Restaurants",
+            "Expenses:Hygiene",
+            "Income:Expenses refunds",
+        ]
+        .iter()
+        .map(|account| account.to_string())
+        .collect();
+        assert_eq!(
+            account_segments("Exp", accounts.clone()),
+            vec!["Expenses", "Expenses:", "Income:Expenses refunds"]
+        );
+        assert_eq!(
+            account_segments("Expenses:", accounts.clone()),
+            vec![
+                "Expenses",
+                "Expenses:Food",
+                "Expenses:Food:",
+                "Expenses:Hygiene",
+                "Income:Expenses refunds"
+            ]
+        );
+        assert_eq!(
+            account_segments("Expenses:Food:", accounts[2..4].to_vec()),
+            vec!["Expenses:Food:Groceries", "Expenses:Food:Restaurants"]
+        );
+    }
+
+    #[test]
+    fn counterparty_completion() {
+        let accounts: Vec<String> = ["Assets:Cash", "Debts:Anna", "Expenses", "Liabilities:Debts"]
+            .iter()
+            .map(|account| account.to_string())
+            .collect();
+        assert_eq!(
+            debts_first("Debts:", accounts.clone()),
+            vec!["Debts:Anna", "Assets:Cash", "Expenses", "Liabilities:Debts"]
+        );
+        assert_eq!(
+            debts_first("Liabilities:", accounts),
+            vec!["Liabilities:Debts", "Assets:Cash", "Debts:Anna", "Expenses"]
+        );
+    }
+
+    #[test]
+    fn alias_completion() {
+        let mut aliases = HashMap::new();
+        aliases.insert("gas".to_owned(), "Expenses:Car:Gas".to_owned());
+        aliases.insert("g".to_owned(), "Expenses:Food:Groceries".to_owned());
+        aliases.insert("rent".to_owned(), "Expenses:Rent".to_owned());
+        assert_eq!(matching_aliases("g", &aliases), vec!["g", "gas"]);
+        assert_eq!(matching_aliases("", &aliases), vec!["g", "gas", "rent"]);
+        assert!(matching_aliases("Exp", &aliases).is_empty());
+    }
+
+    #[test]
+    fn subaccount_completion() {
+        let accounts: Vec<String> = [
+            "Assets:Cash",
+            "Expenses:Food",
+            "Expenses:Food:Groceries",
+            "Expenses:Food:Restaurants:Lunch",
+            "Expenses:Hygiene",
+            "Liabilities:Expenses:Hygiene:Deposit",
+        ]
+        .iter()
+        .map(|account| account.to_string())
+        .collect();
+        assert_eq!(
+            account_candidates("Expenses:Food:", accounts.clone()),
+            vec!["Expenses:Food:Groceries", "Expenses:Food:Restaurants:"]
+        );
+        assert!(account_candidates("Expenses:Hygiene:", accounts.clone()).is_empty());
+        assert!(account_candidates("Assets:Cash:", accounts.clone()).is_empty());
+        assert_eq!(
+            account_candidates("Hygiene", accounts),
+            vec!["Expenses:Hygiene", "Liabilities:Expenses:Hygiene:Deposit"]
+        );
+    }
+
+    #[test]
+    fn history_saving() {
+        let dir = std::env::temp_dir().join(format!("splitter-history-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let history_file = dir.join("history.txt");
+        let config = Config {
+            history_file: history_file.to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let journal = dir.join("journal.ledger").to_string_lossy().into_owned();
+        let mut controller = TUIController::new(journal, config);
+        // Tests don't run in a terminal.
+        controller.plain_input = None;
+        controller.editor.add_history_entry("a Expenses:Food € 5");
+        controller.history_saved = false;
+        drop(controller);
+        let history = std::fs::read_to_string(&history_file).unwrap();
+        std::fs::remove_file(&history_file).unwrap();
+        assert!(history.lines().any(|line| line == "a Expenses:Food € 5"));
+
+        let config = Config {
+            history_file: history_file.to_string_lossy().into_owned(),
+            no_history: true,
+            ..Config::default()
+        };
+        let journal = dir.join("journal.ledger").to_string_lossy().into_owned();
+        let mut controller = TUIController::new(journal, config);
+        controller.editor.add_history_entry("a Expenses:Food € 5");
+        controller.history_saved = false;
+        controller.save_history().unwrap();
+        drop(controller);
+        let saved = history_file.exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!saved);
+    }
+
+    #[test]
+    fn clearing_changes() {
+        let config = Config {
+            no_history: true,
+            ..Config::default()
+        };
+        let journal = std::env::temp_dir()
+            .join(format!("splitter-clear-{}.ledger", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let mut controller = TUIController::new(journal, config);
+        let date = NaiveDate::from_ymd(2020, 3, 2);
+        controller.start_transaction(Transaction::new(date, "Lidl".to_owned()));
+        controller.parse_change("tag trip Rome");
+        controller.parse_change("a Expenses:Food € 5");
+        assert_eq!(controller.current_tx.as_ref().unwrap().changes.len(), 1);
+        controller.parse_change("clear");
+        let tx = controller.current_tx.clone().unwrap();
+        assert_eq!((tx.date, tx.description.as_str()), (date, "Lidl"));
+        assert_eq!(tx.tags, vec![("trip".to_owned(), "Rome".to_owned())]);
+        assert!(tx.changes.is_empty());
+
+        controller.parse_change("u");
+        assert!(controller.current_tx.as_ref().unwrap().changes.is_empty());
+        controller.parse_change("a Expenses:Food € 5");
+        assert_eq!(controller.current_tx.as_ref().unwrap().changes.len(), 1);
+    }
+
+    #[test]
+    fn declined_finalize_and_save() {
+        let config = Config {
+            no_history: true,
+            quick: true,
+            finalize_account: Some("Assets:Checking Account".to_owned()),
+            ..Config::default()
+        };
+        let journal = std::env::temp_dir()
+            .join(format!("splitter-ff-{}.ledger", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let mut controller = TUIController::new(journal, config);
+        // No description is entered when asked for it, declining the save.
+        controller.plain_input = Some(Box::new(|_| Ok(0)));
+        controller.parse_change("a Expenses:Food € 5");
+        controller.parse_change("ff");
+        let tx = controller.current_tx.clone().unwrap();
+        assert!(tx.is_balanced(None));
+        assert_eq!(
+            controller.recent_accounts,
+            vec!["Expenses:Food", "Assets:Checking Account"]
+        );
+
+        controller.parse_change("u");
+        let tx = controller.current_tx.clone().unwrap();
+        assert_eq!(tx.changes.len(), 1);
+        assert!(tx.changes.contains_key("Expenses:Food"));
+    }
+
+    #[test]
+    fn plain_input() {
+        let config = Config {
+            no_history: true,
+            read_only: true,
+            output_date_format: Some("%Y-%m-%d".to_owned()),
+            ..Config::default()
+        };
+        let journal = std::env::temp_dir()
+            .join(format!("splitter-plain-{}.ledger", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let mut controller = TUIController::new(journal, config);
+        let mut input = io::Cursor::new("2020-03-02 Lidl\r\na Expenses:Food € 5\n\n");
+        controller.plain_input = Some(Box::new(move |line| {
+            io::BufRead::read_line(&mut input, line)
+        }));
+        controller.run().unwrap();
+        assert!(controller.current_tx.is_none());
+        assert_eq!(controller.recent_accounts, vec!["Expenses:Food".to_owned()]);
+    }
 }
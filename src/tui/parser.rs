@@ -1,27 +1,157 @@
-use crate::transaction::{Amount, Transaction};
+use crate::transaction::{counterparty, debt_account, pad, Amount, Transaction};
 use anyhow::anyhow;
 use anyhow::Result;
-use chrono::NaiveDate;
+use chrono::{Duration, Local, NaiveDate};
 use lazy_static::lazy_static;
 use regex::Regex;
 use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::fmt;
+use std::iter::once;
 use std::str::FromStr;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Operation<'a> {
     AddSimpleChange(&'a str, Amount),
-    AddSplitChange(&'a str, &'a str, Amount),
-    Finalize(&'a str),
+    /// Adds the amount at the total cost, like `a Expenses CZK 250 ~ € 10`.
+    AddConvertedChange(&'a str, Amount, Amount),
+    /// Splits the amounts between the accounts, in half unless the shares
+    /// of the accounts are given.
+    AddSplitChange(&'a str, &'a str, Vec<Amount>, Option<(Decimal, Decimal)>),
+    /// Splits the amounts between the accounts by their ratios, like
+    /// `s Expenses Debts:Pete:0.3 Debts:Anna:0.2 € 100`.
+    AddRatioSplitChange(Vec<(&'a str, Decimal)>, Vec<Amount>),
+    AddNamedSplitChange(&'a str, Vec<&'a str>, Amount),
+    AddProportionalChange(Vec<(&'a str, Decimal)>, Amount),
+    Finalize(&'a str, Option<&'a str>),
+    AssertBalance(&'a str, Amount),
+    Transfer(&'a str, &'a str, Amount),
+    Lend(&'a str, &'a str, Amount),
 }
 
 impl<'a> Operation<'a> {
-    pub fn add_to_transation(self, tx: &mut Transaction) {
+    /// Returns the amounts the operation adds.
+    pub fn amounts(&self) -> Vec<&Amount> {
+        match self {
+            Operation::AddSimpleChange(_, amount)
+            | Operation::AddNamedSplitChange(_, _, amount)
+            | Operation::AddProportionalChange(_, amount)
+            | Operation::Transfer(_, _, amount)
+            | Operation::Lend(_, _, amount) => vec![amount],
+            Operation::AddConvertedChange(_, amount, cost) => vec![amount, cost],
+            Operation::AddSplitChange(_, _, amounts, _)
+            | Operation::AddRatioSplitChange(_, amounts) => amounts.iter().collect(),
+            Operation::Finalize(_, _) | Operation::AssertBalance(_, _) => Vec::new(),
+        }
+    }
+
+    /// Returns the currency entered last.
+    pub fn currency(&self) -> Option<&str> {
+        match self {
+            Operation::AssertBalance(_, amount) => Some(&amount.0),
+            _ => self.amounts().last().map(|amount| amount.0.as_str()),
+        }
+    }
+
+    /// Returns the accounts the operation was entered with.
+    pub fn accounts(&self) -> Vec<&'a str> {
+        match self {
+            Operation::AddSimpleChange(account, _)
+            | Operation::AddConvertedChange(account, _, _)
+            | Operation::AddNamedSplitChange(account, _, _)
+            | Operation::Finalize(account, _)
+            | Operation::AssertBalance(account, _) => vec![account],
+            Operation::AddSplitChange(account1, account2, _, _)
+            | Operation::Transfer(account1, account2, _)
+            | Operation::Lend(account1, account2, _) => vec![account1, account2],
+            Operation::AddProportionalChange(subtotals, _) => {
+                subtotals.iter().map(|(account, _)| *account).collect()
+            }
+            Operation::AddRatioSplitChange(ratios, _) => {
+                ratios.iter().map(|(account, _)| *account).collect()
+            }
+        }
+    }
+
+    /// Returns the people the split operations divide the amount with, or
+    /// the amount is lent to, whose accounts are under the debt prefix.
+    pub fn counterparties(&self, debt_prefix: &str) -> Vec<String> {
+        match self {
+            Operation::AddSplitChange(_, account, _, _) | Operation::Lend(account, _, _) => {
+                counterparty(debt_prefix, account)
+                    .map(|c| c.to_owned())
+                    .into_iter()
+                    .collect()
+            }
+            Operation::AddRatioSplitChange(ratios, _) => ratios[1..]
+                .iter()
+                .filter_map(|(account, _)| counterparty(debt_prefix, account))
+                .map(|c| c.to_owned())
+                .collect(),
+            Operation::AddNamedSplitChange(_, names, _) => names
+                .iter()
+                .filter_map(|name| {
+                    counterparty(debt_prefix, &debt_account(debt_prefix, name))
+                        .map(|c| c.to_owned())
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Applies the operation to the transaction. Named splits expand names to
+    /// accounts under the debt prefix, shares of split amounts are rounded to
+    /// at least the number of decimal places of their currency.
+    pub fn add_to_transation(
+        self,
+        tx: &mut Transaction,
+        debt_prefix: &str,
+        precision: impl Fn(&str) -> u32,
+    ) {
         match self {
             Operation::AddSimpleChange(account, amount) => tx.add_change(account, amount),
-            Operation::AddSplitChange(account1, account2, amount) => {
-                tx.add_split_change(account1, account2, amount)
+            Operation::AddConvertedChange(account, amount, cost) => {
+                tx.add_converted_change(account, amount, cost)
+            }
+            Operation::AddSplitChange(account1, account2, amounts, shares) => {
+                for amount in amounts {
+                    let precision = precision(&amount.0);
+                    match shares {
+                        Some((share1, share2)) => {
+                            let weights =
+                                [(account1.to_owned(), share1), (account2.to_owned(), share2)];
+                            tx.add_weighted_change(&weights, amount, precision)
+                        }
+                        None => tx.add_split_change(account1, account2, amount, precision),
+                    }
+                }
             }
-            Operation::Finalize(account) => tx.finalize(account),
+            Operation::AddRatioSplitChange(ratios, amounts) => {
+                let weights: Vec<(String, Decimal)> = ratios
+                    .iter()
+                    .map(|(account, ratio)| (account.to_string(), *ratio))
+                    .collect();
+                for amount in amounts {
+                    let precision = precision(&amount.0);
+                    tx.add_weighted_change(&weights, amount, precision)
+                }
+            }
+            Operation::AddNamedSplitChange(account, names, amount) => {
+                let precision = precision(&amount.0);
+                tx.add_named_split_change(account, &names, amount, debt_prefix, precision)
+            }
+            Operation::AddProportionalChange(subtotals, amount) => {
+                let weights: Vec<(String, Decimal)> = subtotals
+                    .iter()
+                    .map(|(account, subtotal)| (account.to_string(), *subtotal))
+                    .collect();
+                let precision = precision(&amount.0);
+                tx.add_weighted_change(&weights, amount, precision)
+            }
+            Operation::Finalize(account, currency) => tx.finalize(account, currency),
+            Operation::AssertBalance(account, amount) => tx.add_assertion(account, amount),
+            Operation::Transfer(from, to, amount) => tx.add_transfer(from, to, amount),
+            Operation::Lend(debtor, from, amount) => tx.add_transfer(from, debtor, amount),
         }
     }
 }
@@ -30,16 +160,23 @@ impl<'a> Operation<'a> {
 pub enum TokenType {
     Operation,
     Account,
+    Name,
     Currency,
     Amount,
-    EOL,
+    Eol,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum OperationType {
     AddSimple,
     AddSplit,
+    AddNamedSplit,
+    AddProportional,
+    AddPercentage,
     Finalize,
+    AssertBalance,
+    Transfer,
+    Lend,
 }
 
 impl OperationType {
@@ -47,64 +184,415 @@ impl OperationType {
         match word {
             "a" => Ok(OperationType::AddSimple),
             "s" => Ok(OperationType::AddSplit),
+            "split" => Ok(OperationType::AddNamedSplit),
+            "tip" => Ok(OperationType::AddProportional),
+            "pct" => Ok(OperationType::AddPercentage),
             "f" => Ok(OperationType::Finalize),
-            _ => Err(anyhow!("Invalid operation type")),
+            "assert" => Ok(OperationType::AssertBalance),
+            "t" => Ok(OperationType::Transfer),
+            "lend" => Ok(OperationType::Lend),
+            _ => Err(anyhow!(
+                "Expecting a command like a, s or f, accounts can't come first"
+            )),
+        }
+    }
+}
+
+/// Checks whether the word is one of the operation keywords, like `a`.
+pub fn is_operation(word: &str) -> bool {
+    OperationType::parse(word).is_ok()
+}
+
+/// Parses an amount, either a decimal number, whole cents suffixed by `c`,
+/// like `1299c` for `12.99`, thousands or millions suffixed by `k` or `m`,
+/// like `1.5k` for `1500`, or a fraction like `1000/3`. Fractions are
+/// rounded to cents, or to the precision of the numerator if it's finer.
+fn parse_number(word: &str) -> Result<Decimal> {
+    if let Some((numerator, denominator)) = word.split_once('/') {
+        let numerator = Decimal::from_str(numerator)?;
+        let denominator = Decimal::from_str(denominator)?;
+        if denominator == Decimal::new(0, 0) {
+            return Err(anyhow!("Division by zero"));
+        }
+        return Ok((numerator / denominator).round_dp(numerator.scale().max(2)));
+    }
+    let multiplier = match word.char_indices().last() {
+        Some((i, 'k')) => Some((&word[..i], 3)),
+        Some((i, 'm')) => Some((&word[..i], 6)),
+        _ => None,
+    };
+    if let Some((number, zeros)) = multiplier {
+        let number = Decimal::from_str(number)?;
+        let multiplied = number
+            .checked_mul(Decimal::from(10i64.pow(zeros)))
+            .ok_or_else(|| anyhow!("Amount is too large"))?;
+        // Multiplying by a power of ten is exact, so this only drops the
+        // decimal places which became zeros.
+        return Ok(multiplied.round_dp(number.scale().saturating_sub(zeros)));
+    }
+    match word.strip_suffix('c') {
+        Some(cents) => match cents.parse::<i64>() {
+            Ok(cents) => Ok(Decimal::new(cents, 2)),
+            Err(_) => Err(anyhow!("Invalid amount in cents")),
+        },
+        None => Ok(Decimal::from_str(word)?),
+    }
+}
+
+/// Splits an account followed by a ratio, like `Debts:Pete:0.3`, into the
+/// account and the ratio. Ratios are written with a decimal point, so that
+/// numbered accounts like `Expenses:2020` aren't taken for them.
+fn split_ratio(word: &str) -> Option<(&str, Decimal)> {
+    let (account, ratio) = word.rsplit_once(':')?;
+    if account.is_empty() || !ratio.contains('.') {
+        return None;
+    }
+    Some((account, Decimal::from_str(ratio).ok()?))
+}
+
+/// Parses the shares of a split, like `2:1`, returning `None` for words which
+/// aren't shares.
+fn parse_shares(word: &str) -> Result<Option<(Decimal, Decimal)>> {
+    let (share1, share2) = match word.split_once(':') {
+        Some(shares) => shares,
+        None => return Ok(None),
+    };
+    match (share1.parse::<u32>(), share2.parse::<u32>()) {
+        (Ok(share1), Ok(share2)) if share1 > 0 && share2 > 0 => {
+            Ok(Some((Decimal::from(share1), Decimal::from(share2))))
         }
+        _ => Err(anyhow!("Shares must be positive whole numbers, like 2:1")),
     }
 }
 
 pub struct Parser<'a> {
     pub next: TokenType,
+    default_currency: Option<&'a str>,
+    default_account: Option<&'a str>,
+    commodity_aliases: Option<&'a HashMap<String, String>>,
+    keywords: Option<&'a HashMap<String, String>>,
+    account_aliases: Option<&'a HashMap<String, String>>,
+    precisions: Option<(&'a HashMap<String, u32>, u32)>,
+    amount_first: bool,
     op_type: Option<OperationType>,
+    negate: bool,
     accounts: Vec<&'a str>,
+    names: Vec<&'a str>,
     currency: Option<&'a str>,
     amount: Option<Decimal>,
+    /// Currency and amount converted to the ones entered after `~`.
+    converted: Option<(&'a str, Decimal)>,
+    /// Currencies and amounts of a split, which can take several of them.
+    split_amounts: Vec<(&'a str, Decimal)>,
+    /// Shares of the split accounts, like `2:1`.
+    shares: Option<(Decimal, Decimal)>,
+    /// Ratios of the accounts a split is shared with, like `0.3` in
+    /// `Debts:Pete:0.3`.
+    ratios: Vec<Decimal>,
+    subtotals: Vec<Decimal>,
 }
 
 impl<'a> Parser<'a> {
     pub fn new() -> Parser<'a> {
+        Parser::with_default_currency(None)
+    }
+
+    /// Creates a parser which uses the default currency when an amount is
+    /// entered in place of the currency. The named split requires an
+    /// explicit currency, since names can't be told apart from currencies.
+    pub fn with_default_currency(default_currency: Option<&'a str>) -> Parser<'a> {
         Parser {
+            default_currency,
+            default_account: None,
+            commodity_aliases: None,
+            keywords: None,
+            account_aliases: None,
+            precisions: None,
+            amount_first: false,
             next: TokenType::Operation,
             op_type: None,
+            negate: false,
             accounts: Vec::new(),
+            names: Vec::new(),
             currency: None,
             amount: None,
+            converted: None,
+            split_amounts: Vec::new(),
+            shares: None,
+            ratios: Vec::new(),
+            subtotals: Vec::new(),
+        }
+    }
+
+    /// Switches the simple and split changes to the receipt order, where the
+    /// currency and amount come before the accounts: `a € 12.34 Expenses`.
+    pub fn amount_first(mut self, amount_first: bool) -> Parser<'a> {
+        self.amount_first = amount_first;
+        self
+    }
+
+    /// Sets the account finalization uses when no account is given.
+    pub fn default_account(mut self, default_account: Option<&'a str>) -> Parser<'a> {
+        self.default_account = default_account;
+        self
+    }
+
+    /// Sets the aliases currencies are replaced with, like `€` for `EUR`.
+    pub fn commodity_aliases(mut self, aliases: &'a HashMap<String, String>) -> Parser<'a> {
+        self.commodity_aliases = Some(aliases);
+        self
+    }
+
+    /// Sets custom keywords of operations, mapped to the built-in ones, like
+    /// `+` to `a`. The built-in keywords keep working.
+    pub fn keywords(mut self, keywords: &'a HashMap<String, String>) -> Parser<'a> {
+        self.keywords = Some(keywords);
+        self
+    }
+
+    /// Sets short aliases of accounts, like `g` for `Expenses:Food:Groceries`.
+    pub fn account_aliases(mut self, aliases: &'a HashMap<String, String>) -> Parser<'a> {
+        self.account_aliases = Some(aliases);
+        self
+    }
+
+    /// Sets the decimal places of commodities, and the default ones, which
+    /// entered amounts are padded to with trailing zeros, so `12.5` and
+    /// `12.50` are both `12.50`. Amounts with more places keep them.
+    pub fn pad_amounts(
+        mut self,
+        precisions: Option<(&'a HashMap<String, u32>, u32)>,
+    ) -> Parser<'a> {
+        self.precisions = precisions;
+        self
+    }
+
+    fn canonical_currency(&self, currency: &'a str) -> &'a str {
+        self.commodity_aliases
+            .and_then(|aliases| aliases.get(currency))
+            .map_or(currency, |commodity| commodity.as_str())
+    }
+
+    fn is_amount_first_op(&self) -> bool {
+        self.amount_first
+            && matches!(
+                self.op_type,
+                Some(OperationType::AddSimple)
+                    | Some(OperationType::AddSplit)
+                    | Some(OperationType::Transfer)
+                    | Some(OperationType::Lend)
+            )
+    }
+
+    /// Whether the next account is the one of the person the amount is split
+    /// with or lent to, like `Debts:Pete` in `s Expenses Debts:Pete € 10`.
+    pub fn expects_counterparty(&self) -> bool {
+        self.next == TokenType::Account
+            && match self.op_type {
+                Some(OperationType::AddSplit) => self.accounts.len() == 1,
+                Some(OperationType::Lend) => self.accounts.is_empty(),
+                _ => false,
+            }
+    }
+
+    /// Whether the operation distributes a total between accounts followed
+    /// by their subtotals or percentages.
+    fn is_proportional(&self) -> bool {
+        matches!(
+            self.op_type,
+            Some(OperationType::AddProportional) | Some(OperationType::AddPercentage)
+        )
+    }
+
+    /// Sum of the percentages entered so far.
+    fn percentage_sum(&self) -> Decimal {
+        self.subtotals.iter().copied().sum()
+    }
+
+    /// Proportional changes take any number of account and subtotal pairs,
+    /// so they can be complete even though more accounts may follow, just
+    /// like splits take any number of currency and amount pairs. Percentages
+    /// have to add up to 100, unless the last account is left without one to
+    /// take the rest. The currency of finalization is optional, as is its
+    /// account when there is a default one.
+    fn is_complete(&self) -> bool {
+        match self.op_type {
+            Some(OperationType::AddProportional) => {
+                self.next == TokenType::Account && !self.subtotals.is_empty()
+            }
+            Some(OperationType::AddPercentage) => {
+                let hundred = Decimal::from(100);
+                match self.next {
+                    TokenType::Account => self.percentage_sum() == hundred,
+                    TokenType::Amount => {
+                        self.accounts.len() > self.subtotals.len()
+                            && self.percentage_sum() < hundred
+                    }
+                    _ => false,
+                }
+            }
+            Some(OperationType::Finalize) => {
+                self.next == TokenType::Currency
+                    || self.next == TokenType::Eol
+                    || (self.next == TokenType::Account && self.default_account.is_some())
+            }
+            Some(OperationType::AddSplit) if !self.split_amounts.is_empty() => {
+                self.next == TokenType::Currency || self.next == TokenType::Eol
+            }
+            _ => self.next == TokenType::Eol,
         }
     }
 
     pub fn operation(self) -> Option<Operation<'a>> {
-        if self.next != TokenType::EOL {
+        if !self.is_complete() {
             return None;
         }
+        let amount = match (self.currency, self.amount) {
+            (Some(currency), Some(amount)) if self.negate => {
+                Some(Amount(currency.to_owned(), -amount))
+            }
+            (Some(currency), Some(amount)) => Some(Amount(currency.to_owned(), amount)),
+            _ => None,
+        };
         let op = match self.op_type.unwrap() {
-            OperationType::AddSimple => Operation::AddSimpleChange(
-                self.accounts[0],
-                Amount(self.currency.unwrap().to_owned(), self.amount.unwrap()),
+            OperationType::AddSimple => match self.converted {
+                Some((currency, converted)) => {
+                    let converted = if self.negate { -converted } else { converted };
+                    Operation::AddConvertedChange(
+                        self.accounts[0],
+                        Amount(currency.to_owned(), converted),
+                        amount.unwrap(),
+                    )
+                }
+                None => Operation::AddSimpleChange(self.accounts[0], amount.unwrap()),
+            },
+            OperationType::AddSplit => {
+                let sign = if self.negate { -1 } else { 1 };
+                let amounts = match self.split_amounts.as_slice() {
+                    [] => vec![amount.unwrap()],
+                    pairs => pairs
+                        .iter()
+                        .map(|(currency, amount)| {
+                            Amount((*currency).to_owned(), *amount * Decimal::from(sign))
+                        })
+                        .collect(),
+                };
+                if self.ratios.is_empty() {
+                    Operation::AddSplitChange(
+                        self.accounts[0],
+                        self.accounts[1],
+                        amounts,
+                        self.shares,
+                    )
+                } else {
+                    // The first account takes the rest.
+                    let rest = Decimal::from(1) - self.ratios.iter().copied().sum::<Decimal>();
+                    let ratios = once((self.accounts[0], rest))
+                        .chain(self.accounts[1..].iter().copied().zip(self.ratios))
+                        .collect();
+                    Operation::AddRatioSplitChange(ratios, amounts)
+                }
+            }
+            OperationType::AddNamedSplit => {
+                Operation::AddNamedSplitChange(self.accounts[0], self.names, amount.unwrap())
+            }
+            OperationType::AddProportional => Operation::AddProportionalChange(
+                self.accounts.into_iter().zip(self.subtotals).collect(),
+                amount.unwrap(),
             ),
-            OperationType::AddSplit => Operation::AddSplitChange(
-                self.accounts[0],
-                self.accounts[1],
-                Amount(self.currency.unwrap().to_owned(), self.amount.unwrap()),
+            OperationType::AddPercentage => {
+                let rest = Decimal::from(100) - self.percentage_sum();
+                let mut percentages: Vec<(&str, Decimal)> = self
+                    .accounts
+                    .iter()
+                    .copied()
+                    .zip(self.subtotals.iter().copied())
+                    .collect();
+                if self.accounts.len() > self.subtotals.len() {
+                    percentages.push((self.accounts[self.accounts.len() - 1], rest));
+                }
+                Operation::AddProportionalChange(percentages, amount.unwrap())
+            }
+            OperationType::Finalize => Operation::Finalize(
+                self.accounts.first().copied().or(self.default_account)?,
+                self.currency,
             ),
-            OperationType::Finalize => Operation::Finalize(self.accounts[0]),
+            OperationType::Transfer => {
+                Operation::Transfer(self.accounts[0], self.accounts[1], amount.unwrap())
+            }
+            OperationType::Lend => {
+                Operation::Lend(self.accounts[0], self.accounts[1], amount.unwrap())
+            }
+            OperationType::AssertBalance => {
+                Operation::AssertBalance(self.accounts[0], amount.unwrap())
+            }
         };
         Some(op)
     }
 
+    /// Parses the whole line, returning the operation when it's a complete
+    /// and valid change command. Nothing else is affected, so any line can be
+    /// checked this way.
+    pub fn validate_change(
+        mut self,
+        line: &'a str,
+    ) -> std::result::Result<Operation<'a>, ParseError> {
+        self.parse_words(line).map_err(ParseError::InvalidWord)?;
+        let next = self.next;
+        self.operation().ok_or(ParseError::Incomplete(next))
+    }
+
+    /// Parses the words of the line, stopping at the first invalid one.
+    pub fn parse_words(&mut self, line: &'a str) -> std::result::Result<(), WordError> {
+        for (start, word) in tokenize(line) {
+            self.parse_word(word)
+                .map_err(|err| WordError::new(line, start, word, err))?;
+        }
+        Ok(())
+    }
+
     pub fn parse_word(&mut self, word: &'a str) -> Result<()> {
         match self.next {
             TokenType::Operation => self.parse_op_type(word)?,
             TokenType::Account => self.parse_account(word)?,
+            TokenType::Name => self.parse_name(word)?,
             TokenType::Currency => self.parse_currency(word)?,
             TokenType::Amount => self.parse_amount(word)?,
-            TokenType::EOL => return Err(anyhow!("Unexpected input at end of line")),
+            TokenType::Eol if word == "~" => self.parse_conversion()?,
+            TokenType::Eol => return Err(anyhow!("Unexpected input at end of line")),
         }
         Ok(())
     }
 
+    /// A `-` after the operation, like `a-`, negates the amount of changes.
     fn parse_op_type(&mut self, word: &'a str) -> Result<()> {
-        self.op_type = Some(OperationType::parse(word)?);
-        self.next = TokenType::Account;
+        let (word, negate) = match word.strip_suffix('-') {
+            Some(word) => (word, true),
+            None => (word, false),
+        };
+        let word = self
+            .keywords
+            .and_then(|keywords| keywords.get(word))
+            .map_or(word, |keyword| keyword.as_str());
+        let op_type = OperationType::parse(word)?;
+        if negate
+            && !matches!(
+                op_type,
+                OperationType::AddSimple
+                    | OperationType::AddSplit
+                    | OperationType::AddNamedSplit
+                    | OperationType::Transfer
+            )
+        {
+            return Err(anyhow!("Only a, s, split and t amounts can be negated"));
+        }
+        self.op_type = Some(op_type);
+        self.negate = negate;
+        if self.is_proportional() || self.is_amount_first_op() {
+            self.next = TokenType::Currency;
+        } else {
+            self.next = TokenType::Account;
+        }
         Ok(())
     }
 
@@ -113,27 +601,145 @@ impl<'a> Parser<'a> {
             static ref ACC_RE: Regex =
                 Regex::new("^[\\p{L}&&[^:digit:]][\\p{L}[:digit:]:]*$").unwrap();
         }
+        let counterparty =
+            self.op_type == Some(OperationType::AddSplit) && self.accounts.len() == 1;
+        if let (true, Some((account, ratio))) = (counterparty, split_ratio(word)) {
+            return self.parse_ratio_account(account, ratio);
+        }
+        let word = self
+            .account_aliases
+            .and_then(|aliases| aliases.get(word))
+            .map_or(word, |account| account.as_str());
         if ACC_RE.is_match(word) {
             self.accounts.push(word);
         } else {
             return Err(anyhow!("Account name contains invalid character"));
         }
-        if self.op_type == Some(OperationType::AddSplit) && self.accounts.len() == 1 {
+        let two_accounts = matches!(
+            self.op_type,
+            Some(OperationType::AddSplit)
+                | Some(OperationType::Transfer)
+                | Some(OperationType::Lend)
+        );
+        if two_accounts && self.accounts.len() == 1 {
             self.next = TokenType::Account;
-        } else if self.op_type == Some(OperationType::Finalize) {
-            self.next = TokenType::EOL;
+        } else if self.op_type == Some(OperationType::AddNamedSplit) {
+            self.next = TokenType::Name;
+        } else if self.is_amount_first_op() {
+            self.next = TokenType::Eol;
+        } else if self.is_proportional() {
+            self.next = TokenType::Amount;
         } else {
             self.next = TokenType::Currency;
         }
         Ok(())
     }
 
+    /// An account of a split followed by its ratio, like `Debts:Pete:0.3`.
+    /// More of them can follow, the first account of the split takes the
+    /// rest of the amount.
+    fn parse_ratio_account(&mut self, account: &'a str, ratio: Decimal) -> Result<()> {
+        if self.is_amount_first_op() {
+            return Err(anyhow!("Ratios can't be given when amounts come first"));
+        }
+        if ratio <= Decimal::new(0, 0) {
+            return Err(anyhow!("Ratio must be positive"));
+        }
+        if self.ratios.iter().copied().sum::<Decimal>() + ratio > Decimal::from(1) {
+            return Err(anyhow!("Ratios add up to more than 1"));
+        }
+        self.parse_account(account)?;
+        self.ratios.push(ratio);
+        self.next = TokenType::Currency;
+        Ok(())
+    }
+
+    /// Names are followed by a currency, which can look just like a name
+    /// (think `CZK`). Such a currency is only recognized once the amount
+    /// following it arrives.
+    fn parse_name(&mut self, word: &'a str) -> Result<()> {
+        lazy_static! {
+            static ref NAME_RE: Regex = Regex::new("^\\p{L}[\\p{L}[:digit:]]*$").unwrap();
+        }
+        if NAME_RE.is_match(word) {
+            self.names.push(word);
+            return Ok(());
+        }
+        if let Ok(amount) = parse_number(word) {
+            if self.names.len() < 2 {
+                return Err(anyhow!("Expecting at least one name and a currency"));
+            }
+            let currency = self.names.pop().unwrap();
+            self.parse_currency(currency)?;
+            self.amount = Some(amount);
+            self.next = TokenType::Eol;
+            return Ok(());
+        }
+        if self.names.is_empty() {
+            return Err(anyhow!("Name contains invalid characters"));
+        }
+        self.parse_currency(word)
+    }
+
+    /// A `~` after the amount of a simple change starts the amount it's
+    /// converted to, which is its total cost in ledger.
+    fn parse_conversion(&mut self) -> Result<()> {
+        if self.op_type != Some(OperationType::AddSimple)
+            || self.is_amount_first_op()
+            || self.converted.is_some()
+        {
+            return Err(anyhow!("Only an amount added by a can be converted"));
+        }
+        self.converted = self.currency.zip(self.amount);
+        self.currency = None;
+        self.amount = None;
+        self.next = TokenType::Currency;
+        Ok(())
+    }
+
+    /// Currencies can't contain digits, unless they're quoted like ledger
+    /// requires, as in `"FUND2030"`. The amounts of a split can be followed
+    /// by the shares of its accounts instead, like `2:1`.
     fn parse_currency(&mut self, word: &'a str) -> Result<()> {
+        if self.op_type == Some(OperationType::AddSplit)
+            && !self.ratios.is_empty()
+            && self.split_amounts.is_empty()
+        {
+            if let Some((account, ratio)) = split_ratio(word) {
+                return self.parse_ratio_account(account, ratio);
+            }
+        }
+        if self.op_type == Some(OperationType::AddSplit)
+            && !self.split_amounts.is_empty()
+            && self.ratios.is_empty()
+        {
+            if let Some(shares) = parse_shares(word)? {
+                self.shares = Some(shares);
+                self.next = TokenType::Eol;
+                return Ok(());
+            }
+        }
         lazy_static! {
-            static ref CURR_RE: Regex = Regex::new("^[^0-9]+$").unwrap();
+            static ref CURR_RE: Regex = Regex::new("^[^0-9]+$|^\"[^\"]+\"$").unwrap();
+        }
+        if self.op_type == Some(OperationType::Finalize) {
+            if !CURR_RE.is_match(word) {
+                return Err(anyhow!("Currency contains invalid characters"));
+            }
+            self.currency = Some(self.canonical_currency(word));
+            self.next = TokenType::Eol;
+            return Ok(());
         }
         if CURR_RE.is_match(word) {
-            self.currency = Some(word);
+            self.currency = Some(self.canonical_currency(word));
+        } else if let (Some(currency), Ok(_), true) = (
+            self.default_currency,
+            parse_number(word),
+            self.split_amounts.is_empty(),
+        ) {
+            self.currency = Some(currency);
+            self.next = TokenType::Amount;
+            return self.parse_amount(word);
         } else {
             return Err(anyhow!("Currency contains invalid characters"));
         }
@@ -141,32 +747,248 @@ impl<'a> Parser<'a> {
         Ok(())
     }
 
+    /// Percentages can be written with a `%` sign, like `30%`.
     fn parse_amount(&mut self, word: &'a str) -> Result<()> {
-        self.amount = Some(Decimal::from_str(word)?);
-        self.next = TokenType::EOL;
+        let percentage =
+            self.op_type == Some(OperationType::AddPercentage) && self.amount.is_some();
+        let amount = match word.strip_suffix('%') {
+            Some(number) if percentage => parse_number(number)?,
+            _ => parse_number(word)?,
+        };
+        let amount = match (self.precisions, self.currency) {
+            (Some((precisions, default_precision)), Some(currency)) if !percentage => {
+                let places = precisions
+                    .get(currency)
+                    .copied()
+                    .unwrap_or(default_precision);
+                pad(amount, places)
+            }
+            _ => amount,
+        };
+        if self.is_amount_first_op() {
+            self.amount = Some(amount);
+            self.next = TokenType::Account;
+        } else if self.op_type == Some(OperationType::AddSplit) {
+            self.split_amounts.push((self.currency.unwrap(), amount));
+            self.next = TokenType::Currency;
+        } else if !self.is_proportional() {
+            self.amount = Some(amount);
+            self.next = TokenType::Eol;
+        } else if self.amount.is_none() {
+            self.amount = Some(amount);
+            self.next = TokenType::Account;
+        } else if amount <= Decimal::new(0, 0) {
+            return Err(anyhow!("Subtotal must be positive"));
+        } else if percentage && self.percentage_sum() + amount > Decimal::from(100) {
+            return Err(anyhow!("Percentages add up to more than 100"));
+        } else {
+            self.subtotals.push(amount);
+            self.next = TokenType::Account;
+        }
         Ok(())
     }
 }
 
-pub fn parse_transaction_header(line: &str) -> Result<Transaction> {
-    let fields: Vec<&str> = line.split_ascii_whitespace().collect();
+/// The reason a line isn't a valid change command.
+#[derive(Debug)]
+pub enum ParseError {
+    /// A word of the line is invalid.
+    InvalidWord(WordError),
+    /// The line ended before the command was complete, expecting the token.
+    Incomplete(TokenType),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::InvalidWord(err) => write!(f, "{}", err),
+            ParseError::Incomplete(next) => {
+                write!(f, "Invalid change command, expecting {:?}", next)
+            }
+        }
+    }
+}
+
+/// An error in a word of a line, pointing to the word and its column.
+#[derive(Debug)]
+pub struct WordError {
+    pub word: String,
+    /// 1-based column of the first character of the word.
+    pub column: usize,
+    pub error: anyhow::Error,
+}
+
+impl WordError {
+    /// Creates the error for the word starting at the byte offset.
+    pub fn new(line: &str, start: usize, word: &str, error: anyhow::Error) -> WordError {
+        WordError {
+            word: word.to_owned(),
+            column: line[..start].chars().count() + 1,
+            error,
+        }
+    }
+}
+
+impl fmt::Display for WordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} '{}' at column {}",
+            self.error, self.word, self.column
+        )
+    }
+}
+
+/// Splits the line into words separated by ASCII whitespace, returning each
+/// word together with the byte offset it starts at. Whitespace between double
+/// quotes doesn't separate words, so `"Fund 2030"` is a single word.
+pub fn tokenize(line: &str) -> Vec<(usize, &str)> {
+    let mut tokens = Vec::new();
+    let mut word_start = None;
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        match (c.is_ascii_whitespace() && !quoted, word_start) {
+            (true, Some(start)) => {
+                tokens.push((start, &line[start..i]));
+                word_start = None;
+            }
+            (false, None) => word_start = Some(i),
+            _ => {}
+        }
+    }
+    if let Some(start) = word_start {
+        tokens.push((start, &line[start..]));
+    }
+    tokens
+}
+
+/// Returns the start and text of the word the cursor is in or right after,
+/// or an empty word at the cursor when it's between words.
+pub fn word_at(line: &str, pos: usize) -> (usize, &str) {
+    tokenize(line)
+        .into_iter()
+        .find(|(start, word)| *start <= pos && pos <= start + word.len())
+        .map_or((pos, ""), |(start, word)| (start, &word[..pos - start]))
+}
+
+/// Replaces references to recently used accounts, like `@2`, with the
+/// accounts. References are numbered from 1.
+pub fn expand_recent(line: &str, recent: &[String]) -> Result<String> {
+    let mut expanded = String::new();
+    let mut copied = 0;
+    for (start, word) in tokenize(line) {
+        let index = match word.strip_prefix('@').and_then(|i| i.parse::<usize>().ok()) {
+            Some(index) => index,
+            None => continue,
+        };
+        let account = index
+            .checked_sub(1)
+            .and_then(|i| recent.get(i))
+            .ok_or_else(|| anyhow!("No recent account {}", word))?;
+        expanded.push_str(&line[copied..start]);
+        expanded.push_str(account);
+        copied = start + word.len();
+    }
+    expanded.push_str(&line[copied..]);
+    Ok(expanded)
+}
+
+/// Returns the words of the line.
+pub fn words(line: &str) -> Vec<&str> {
+    tokenize(line).into_iter().map(|(_, word)| word).collect()
+}
+
+/// Parses a date in the given format, one of the `today` (or `.`) and
+/// `yesterday` shortcuts or a signed number of days relative to today.
+fn parse_date(word: &str, date_format: &str, today: NaiveDate) -> Result<NaiveDate> {
+    let parse_error = match parse_named_date(word, date_format, today) {
+        Ok(date) => return Ok(date),
+        Err(err) => err,
+    };
+    match word.parse::<i32>() {
+        Ok(offset) => today
+            .checked_add_signed(Duration::days(offset.into()))
+            .ok_or_else(|| anyhow!("Date offset out of range")),
+        Err(_) => Err(parse_error),
+    }
+}
+
+/// Parses a date in the given format or one of the `today` (or `.`) and
+/// `yesterday` shortcuts, but not a relative one.
+fn parse_named_date(word: &str, date_format: &str, today: NaiveDate) -> Result<NaiveDate> {
+    match word {
+        "." | "today" => Ok(today),
+        "yesterday" => Ok(today.pred()),
+        _ => Ok(NaiveDate::parse_from_str(word, date_format)?),
+    }
+}
+
+/// Parses the argument of the `date` command, accepting the same dates as the
+/// transaction header.
+pub fn parse_header_date(words: &[&str], date_format: &str) -> Result<NaiveDate> {
+    match words {
+        [word] => parse_date(word, date_format, Local::today().naive_local()),
+        _ => Err(anyhow!("Expecting a single date")),
+    }
+}
+
+pub fn parse_description(words: &[&str]) -> String {
+    words.join(" ")
+}
+
+/// Parses the arguments of the `tag` command, a key followed by the value.
+pub fn parse_tag(words: &[&str]) -> Result<(String, String)> {
+    match words {
+        [key, value @ ..] if !value.is_empty() && !key.contains(':') => {
+            Ok(((*key).to_owned(), value.join(" ")))
+        }
+        _ => Err(anyhow!("Expecting a tag name and its value")),
+    }
+}
+
+/// Parses a header, the date followed by the description. When a sticky date
+/// is given, a header which doesn't start with a date is just a description
+/// of a transaction on that date.
+pub fn parse_transaction_header(
+    line: &str,
+    date_format: &str,
+    sticky_date: Option<NaiveDate>,
+) -> Result<Transaction> {
+    let fields = words(line);
     if fields.is_empty() {
         return Err(anyhow!("No transaction header provided"));
     }
-    let date: NaiveDate = fields[0].parse()?;
-    let description = fields[1..fields.len()].join(" ");
-    Ok(Transaction::new(date, description))
+    // With a sticky date, descriptions like "3 coffees" are more likely than
+    // relative dates.
+    let parse = if sticky_date.is_some() {
+        parse_named_date
+    } else {
+        parse_date
+    };
+    match (
+        parse(fields[0], date_format, Local::today().naive_local()),
+        sticky_date,
+    ) {
+        (Ok(date), _) => Ok(Transaction::new(date, parse_description(&fields[1..]))),
+        (Err(_), Some(date)) => Ok(Transaction::new(date, parse_description(&fields))),
+        (Err(err), None) => Err(err),
+    }
 }
 
 mod test {
     #[allow(unused_imports)]
     use super::*;
     #[allow(unused_imports)]
+    use crate::transaction::DEBTS_PREFIX;
+    #[allow(unused_imports)]
     use rust_decimal_macros::*;
 
     #[test]
     fn parse_simple() {
-        let line = vec!["a", "Expenses", "€", "12.34"];
+        let line = ["a", "Expenses", "€", "12.34"];
         let mut parser = Parser::new();
         assert_eq!(parser.next, TokenType::Operation);
         assert!(parser.parse_word(line[0]).is_ok());
@@ -176,7 +998,7 @@ mod test {
         assert!(parser.parse_word(line[2]).is_ok());
         assert_eq!(parser.next, TokenType::Amount);
         assert!(parser.parse_word(line[3]).is_ok());
-        assert_eq!(parser.next, TokenType::EOL);
+        assert_eq!(parser.next, TokenType::Eol);
         assert!(parser.parse_word("blah").is_err());
         assert_eq!(
             parser.operation().unwrap(),
@@ -186,7 +1008,7 @@ mod test {
 
     #[test]
     fn parse_split() {
-        let line = vec!["s", "Expenses", "Debts:Peter", "CZK", "120.50"];
+        let line = ["s", "Expenses", "Debts:Peter", "CZK", "120.50"];
         let mut parser = Parser::new();
         assert_eq!(parser.next, TokenType::Operation);
         assert!(parser.parse_word(line[0]).is_ok());
@@ -198,56 +1020,1013 @@ mod test {
         assert!(parser.parse_word(line[3]).is_ok());
         assert_eq!(parser.next, TokenType::Amount);
         assert!(parser.parse_word(line[4]).is_ok());
-        assert_eq!(parser.next, TokenType::EOL);
-        assert!(parser.parse_word("blah").is_err());
+        assert_eq!(parser.next, TokenType::Currency);
+        assert!(parser.parse_word("12").is_err());
+        let operation = parser.operation().unwrap();
         assert_eq!(
-            parser.operation().unwrap(),
+            operation,
             Operation::AddSplitChange(
                 "Expenses",
                 "Debts:Peter",
-                Amount("CZK".to_owned(), dec!(120.50))
+                vec![Amount("CZK".to_owned(), dec!(120.50))],
+                None
             )
         );
+        assert_eq!(operation.counterparties(DEBTS_PREFIX), vec!["Peter"]);
     }
 
     #[test]
-    fn parse_finalize() {
-        let line = vec!["f", "Accounts:Checking"];
+    fn parse_multi_currency_split() {
+        let mut parser = Parser::with_default_currency(Some("€"));
+        parser
+            .parse_words("s- Expenses Debts:Pete € 20 USD 10.01")
+            .unwrap();
+        let operation = parser.operation().unwrap();
+        assert_eq!(
+            operation,
+            Operation::AddSplitChange(
+                "Expenses",
+                "Debts:Pete",
+                vec![
+                    Amount("€".to_owned(), dec!(-20)),
+                    Amount("USD".to_owned(), dec!(-10.01))
+                ],
+                None
+            )
+        );
+        assert_eq!(operation.currency(), Some("USD"));
+
+        let mut tx = Transaction::new(NaiveDate::from_ymd(2020, 1, 10), "Duty free".to_owned());
+        operation.add_to_transation(&mut tx, DEBTS_PREFIX, |_| 2);
+        assert_eq!(
+            tx.balance(),
+            vec![
+                Amount("USD".to_owned(), dec!(-10.01)),
+                Amount("€".to_owned(), dec!(-20))
+            ]
+        );
+
+        let mut parser = Parser::with_default_currency(Some("€"));
+        assert!(parser.parse_words("s Expenses Debts:Pete 20 10").is_err());
         let mut parser = Parser::new();
-        assert_eq!(parser.next, TokenType::Operation);
-        assert!(parser.parse_word(line[0]).is_ok());
-        assert_eq!(parser.next, TokenType::Account);
-        assert!(parser.parse_word(line[1]).is_ok());
-        assert_eq!(parser.next, TokenType::EOL);
-        assert!(parser.parse_word("blah").is_err());
+        parser
+            .parse_words("s Expenses Debts:Pete € 20 USD")
+            .unwrap();
+        assert!(parser.operation().is_none());
+    }
+
+    #[test]
+    fn multi_currency_split_postings() {
+        let mut parser = Parser::new();
+        parser
+            .parse_words("s Expenses Debts:Pete € 20 CZK 200")
+            .unwrap();
+        let mut tx = Transaction::new(NaiveDate::from_ymd(2020, 1, 10), "Dinner".to_owned());
+        parser
+            .operation()
+            .unwrap()
+            .add_to_transation(&mut tx, DEBTS_PREFIX, |_| 2);
         assert_eq!(
-            parser.operation().unwrap(),
-            Operation::Finalize("Accounts:Checking")
+            tx.changes["Debts:Pete"],
+            vec![
+                Amount("CZK".to_owned(), dec!(100)),
+                Amount("€".to_owned(), dec!(10))
+            ]
+        );
+        // Ledger allows a single amount per posting, so each currency gets
+        // its own.
+        assert_eq!(
+            tx.to_string(),
+            "2020/01/10 Dinner\n\
+             \tDebts:Pete  CZK 100\n\
+             \tDebts:Pete  € 10\n\
+             \tExpenses    CZK 100\n\
+             \tExpenses    € 10\n"
         );
     }
 
     #[test]
-    fn test_errors() {
+    fn parse_split_shares() {
         let mut parser = Parser::new();
-        assert!(parser.parse_word("x").is_err());
-        assert!(parser.parse_word("a").is_ok());
-        assert!(parser.parse_word("123").is_err());
-        assert!(parser.parse_word("1checking").is_err());
-        assert!(parser.parse_word("Expenses").is_ok());
-        assert!(parser.parse_word("123").is_err());
-        assert!(parser.parse_word("€").is_ok());
-        assert!(parser.parse_word("1a2b").is_err());
-        assert!(parser.parse_word("12.30").is_ok());
+        parser
+            .parse_words("s Expenses Debts:Pete € 30 USD 10 2:1")
+            .unwrap();
+        assert_eq!(parser.next, TokenType::Eol);
+        let operation = parser.operation().unwrap();
         assert_eq!(
-            parser.operation().unwrap(),
-            Operation::AddSimpleChange("Expenses", Amount("€".to_owned(), dec!(12.30)))
+            operation,
+            Operation::AddSplitChange(
+                "Expenses",
+                "Debts:Pete",
+                vec![
+                    Amount("€".to_owned(), dec!(30)),
+                    Amount("USD".to_owned(), dec!(10))
+                ],
+                Some((dec!(2), dec!(1)))
+            )
+        );
+
+        let mut tx = Transaction::new(NaiveDate::from_ymd(2020, 1, 10), "Pizza".to_owned());
+        operation.add_to_transation(&mut tx, DEBTS_PREFIX, |_| 2);
+        assert_eq!(
+            tx.changes["Expenses"],
+            vec![
+                Amount("USD".to_owned(), dec!(6.67)),
+                Amount("€".to_owned(), dec!(20))
+            ]
+        );
+        assert_eq!(
+            tx.changes["Debts:Pete"],
+            vec![
+                Amount("USD".to_owned(), dec!(3.33)),
+                Amount("€".to_owned(), dec!(10))
+            ]
+        );
+
+        let mut parser = Parser::new();
+        assert!(parser
+            .parse_words("s Expenses Debts:Pete € 30 0:1")
+            .is_err());
+        let mut parser = Parser::new();
+        assert!(parser
+            .parse_words("s Expenses Debts:Pete € 30 2:1 USD")
+            .is_err());
+        let mut parser = Parser::new();
+        assert!(parser.parse_words("s Expenses Debts:Pete 2:1").is_err());
+    }
+
+    #[test]
+    fn parse_conversion() {
+        let mut parser = Parser::new();
+        parser
+            .parse_words("a- Expenses:Travel CZK 250 ~ € 10")
+            .unwrap();
+        let operation = parser.operation().unwrap();
+        assert_eq!(
+            operation,
+            Operation::AddConvertedChange(
+                "Expenses:Travel",
+                Amount("CZK".to_owned(), dec!(-250)),
+                Amount("€".to_owned(), dec!(-10))
+            )
         );
+        assert_eq!(operation.currency(), Some("€"));
+
+        let mut tx = Transaction::new(NaiveDate::from_ymd(2020, 1, 10), "Prague".to_owned());
+        Parser::new()
+            .validate_change("a Expenses:Travel CZK 250 ~ € 10")
+            .unwrap()
+            .add_to_transation(&mut tx, DEBTS_PREFIX, |_| 2);
+        assert_eq!(tx.balance(), vec![Amount("€".to_owned(), dec!(10))]);
+
+        let mut parser = Parser::with_default_currency(Some("€"));
+        assert!(parser.parse_words("a Expenses CZK 250 ~ 10").is_ok());
+        assert!(parser.operation().is_some());
+        let mut parser = Parser::new();
+        assert!(parser.parse_words("a Expenses CZK 250 ~").is_ok());
+        assert!(parser.operation().is_none());
+        let invalid = [
+            "a Expenses CZK 250 ~ € 10 ~ $ 11",
+            "t Assets Expenses CZK 250 ~ € 10",
+            "a Expenses CZK 250 €",
+        ];
+        for line in invalid.iter() {
+            assert!(Parser::new().parse_words(line).is_err(), "{}", line);
+        }
     }
 
     #[test]
-    fn tx_header() {
-        let tx = parse_transaction_header("2020-02-27 Test transaction").unwrap();
+    fn counterparty_position() {
+        let expects_counterparty = |words: &[&str], amount_first: bool| {
+            let mut parser = Parser::new().amount_first(amount_first);
+            for word in words {
+                parser.parse_word(word).unwrap();
+            }
+            parser.expects_counterparty()
+        };
+        assert!(!expects_counterparty(&["s"], false));
+        assert!(expects_counterparty(&["s", "Expenses"], false));
+        assert!(expects_counterparty(&["s", "€", "10", "Expenses"], true));
+        assert!(expects_counterparty(&["lend"], false));
+        assert!(!expects_counterparty(&["lend", "Debts:Pete"], false));
+        assert!(!expects_counterparty(&["t", "Assets:Cash"], false));
+        assert!(!expects_counterparty(&["a"], false));
+    }
+
+    #[test]
+    fn validate_changes() {
+        assert_eq!(
+            Parser::new().validate_change("a Expenses € 5").unwrap(),
+            Operation::AddSimpleChange("Expenses", Amount("€".to_owned(), dec!(5)))
+        );
+        match Parser::new().validate_change("a Expenses € 5x") {
+            Err(ParseError::InvalidWord(err)) => {
+                assert_eq!(err.word, "5x");
+                assert_eq!(err.column, 14);
+            }
+            result => panic!("Unexpected {:?}", result),
+        }
+        match Parser::new().validate_change("t Assets:Checking Assets:Cash") {
+            Err(err @ ParseError::Incomplete(TokenType::Currency)) => assert_eq!(
+                err.to_string(),
+                "Invalid change command, expecting Currency"
+            ),
+            result => panic!("Unexpected {:?}", result),
+        }
+        assert!(Parser::with_default_currency(Some("€"))
+            .validate_change("a Expenses 5")
+            .is_ok());
+    }
+
+    #[test]
+    fn parse_ratio_split() {
+        let mut parser = Parser::new();
+        parser
+            .parse_words("s Expenses Debts:Pete:0.3 Debts:Anna:.2 € 100.01")
+            .unwrap();
+        let operation = parser.operation().unwrap();
+        assert_eq!(
+            operation,
+            Operation::AddRatioSplitChange(
+                vec![
+                    ("Expenses", dec!(0.5)),
+                    ("Debts:Pete", dec!(0.3)),
+                    ("Debts:Anna", dec!(0.2))
+                ],
+                vec![Amount("€".to_owned(), dec!(100.01))]
+            )
+        );
+        assert_eq!(operation.counterparties(DEBTS_PREFIX), vec!["Pete", "Anna"]);
+
+        let mut tx = Transaction::new(NaiveDate::from_ymd(2020, 1, 10), "Dinner".to_owned());
+        operation.add_to_transation(&mut tx, DEBTS_PREFIX, |_| 2);
+        let shares: Vec<Amount> = ["Expenses", "Debts:Pete", "Debts:Anna"]
+            .iter()
+            .map(|account| tx.changes[*account][0].clone())
+            .collect();
+        assert_eq!(
+            shares,
+            vec![
+                Amount("€".to_owned(), dec!(50.01)),
+                Amount("€".to_owned(), dec!(30.00)),
+                Amount("€".to_owned(), dec!(20.00))
+            ]
+        );
+        assert_eq!(tx.balance(), vec![Amount("€".to_owned(), dec!(100.01))]);
+
+        let mut parser = Parser::new();
+        parser
+            .parse_words("s Expenses Debts:Pete:0.5 Debts:Anna:0.5 € 10")
+            .unwrap();
+        assert!(parser.operation().is_some());
+        let mut parser = Parser::new();
+        assert!(parser
+            .parse_words("s Expenses Debts:Pete:0.7 Debts:Anna:0.4 € 10")
+            .is_err());
+        let mut parser = Parser::new();
+        assert!(parser
+            .parse_words("s Expenses Debts:Pete:0.3 € 10 2:1")
+            .is_err());
+        let mut parser = Parser::new();
+        parser
+            .parse_words("s Expenses:2020 Debts:2020 € 10")
+            .unwrap();
+        assert!(matches!(
+            parser.operation(),
+            Some(Operation::AddSplitChange(_, "Debts:2020", _, None))
+        ));
+    }
+
+    #[test]
+    fn parse_lend() {
+        let mut parser = Parser::new();
+        parser
+            .parse_words("lend Debts:Pete Assets:Cash € 50")
+            .unwrap();
+        let operation = parser.operation().unwrap();
+        assert_eq!(
+            operation,
+            Operation::Lend(
+                "Debts:Pete",
+                "Assets:Cash",
+                Amount("€".to_owned(), dec!(50))
+            )
+        );
+        assert_eq!(operation.counterparties(DEBTS_PREFIX), vec!["Pete"]);
+
+        let mut tx = Transaction::new(NaiveDate::from_ymd(2020, 1, 10), "Loan".to_owned());
+        operation.add_to_transation(&mut tx, DEBTS_PREFIX, |_| 2);
+        assert_eq!(
+            tx.changes["Debts:Pete"],
+            vec![Amount("€".to_owned(), dec!(50))]
+        );
+        assert_eq!(
+            tx.changes["Assets:Cash"],
+            vec![Amount("€".to_owned(), dec!(-50))]
+        );
+        assert!(tx.is_balanced(None));
+        assert!(Parser::new().parse_word("lend-").is_err());
+    }
+
+    #[test]
+    fn parse_transfer() {
+        let mut parser = Parser::new();
+        assert!(parser
+            .parse_words("t Assets:Cash Assets:Checking € 50")
+            .is_ok());
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::Transfer(
+                "Assets:Cash",
+                "Assets:Checking",
+                Amount("€".to_owned(), dec!(50))
+            )
+        );
+
+        let mut parser = Parser::new().amount_first(true);
+        assert!(parser
+            .parse_words("t € 50 Assets:Cash Assets:Checking")
+            .is_ok());
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::Transfer(
+                "Assets:Cash",
+                "Assets:Checking",
+                Amount("€".to_owned(), dec!(50))
+            )
+        );
+    }
+
+    #[test]
+    fn parse_negated() {
+        let mut parser = Parser::new();
+        assert!(parser.parse_words("a- Income:Salary € 50").is_ok());
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::AddSimpleChange("Income:Salary", Amount("€".to_owned(), dec!(-50)))
+        );
+
+        let mut parser = Parser::with_default_currency(Some("€"));
+        assert!(parser.parse_words("s- Income Debts:Anna 10").is_ok());
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::AddSplitChange(
+                "Income",
+                "Debts:Anna",
+                vec![Amount("€".to_owned(), dec!(-10))],
+                None
+            )
+        );
+
+        let mut parser = Parser::new();
+        assert!(parser.parse_words("a Expenses € 5").is_ok());
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::AddSimpleChange("Expenses", Amount("€".to_owned(), dec!(5)))
+        );
+        assert!(Parser::new().parse_word("f-").is_err());
+        assert!(Parser::new().parse_word("tip-").is_err());
+    }
+
+    #[test]
+    fn parse_named_split() {
+        let line = ["split", "Expenses:Food", "anna", "bob", "CZK", "60"];
+        let mut parser = Parser::new();
+        assert!(parser.parse_word(line[0]).is_ok());
+        assert_eq!(parser.next, TokenType::Account);
+        assert!(parser.parse_word(line[1]).is_ok());
+        assert_eq!(parser.next, TokenType::Name);
+        assert!(parser.parse_word(line[2]).is_ok());
+        assert_eq!(parser.next, TokenType::Name);
+        assert!(parser.parse_word(line[3]).is_ok());
+        assert_eq!(parser.next, TokenType::Name);
+        assert!(parser.parse_word(line[4]).is_ok());
+        assert_eq!(parser.next, TokenType::Name);
+        assert!(parser.parse_word(line[5]).is_ok());
+        assert_eq!(parser.next, TokenType::Eol);
+        let operation = parser.operation().unwrap();
+        assert_eq!(
+            operation,
+            Operation::AddNamedSplitChange(
+                "Expenses:Food",
+                vec!["anna", "bob"],
+                Amount("CZK".to_owned(), dec!(60))
+            )
+        );
+        assert_eq!(operation.counterparties(DEBTS_PREFIX), vec!["Anna", "Bob"]);
+
+        let line = ["split", "Expenses:Food", "anna", "€", "60"];
+        let mut parser = Parser::new();
+        for word in &line[..4] {
+            assert!(parser.parse_word(word).is_ok());
+        }
+        assert_eq!(parser.next, TokenType::Amount);
+        assert!(parser.parse_word(line[4]).is_ok());
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::AddNamedSplitChange(
+                "Expenses:Food",
+                vec!["anna"],
+                Amount("€".to_owned(), dec!(60))
+            )
+        );
+
+        let mut parser = Parser::new();
+        assert!(parser.parse_word("split").is_ok());
+        assert!(parser.parse_word("Expenses").is_ok());
+        assert!(parser.parse_word("€").is_err());
+        assert!(parser.parse_word("CZK").is_ok());
+        assert!(parser.parse_word("60").is_err());
+    }
+
+    #[test]
+    fn parse_proportional() {
+        let line = ["tip", "€", "72", "Debts:Anna", "30", "Expenses:Food", "10"];
+        let mut parser = Parser::new();
+        assert!(parser.parse_word(line[0]).is_ok());
+        assert_eq!(parser.next, TokenType::Currency);
+        assert!(parser.parse_word(line[1]).is_ok());
+        assert_eq!(parser.next, TokenType::Amount);
+        assert!(parser.parse_word(line[2]).is_ok());
+        assert_eq!(parser.next, TokenType::Account);
+        assert!(!parser.is_complete());
+        assert!(parser.parse_word(line[3]).is_ok());
+        assert_eq!(parser.next, TokenType::Amount);
+        assert!(parser.parse_word(line[4]).is_ok());
+        assert_eq!(parser.next, TokenType::Account);
+        assert!(parser.is_complete());
+        assert!(parser.parse_word(line[5]).is_ok());
+        assert!(!parser.is_complete());
+        assert!(parser.parse_word("0").is_err());
+        assert!(parser.parse_word(line[6]).is_ok());
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::AddProportionalChange(
+                vec![("Debts:Anna", dec!(30)), ("Expenses:Food", dec!(10))],
+                Amount("€".to_owned(), dec!(72))
+            )
+        );
+    }
+
+    #[test]
+    fn parse_percentages() {
+        let mut parser = Parser::new();
+        parser
+            .parse_words("pct € 90 Expenses:Food 50% Debts:Anna 30 Debts:Bob")
+            .unwrap();
+        assert_eq!(parser.next, TokenType::Amount);
+        let operation = parser.operation().unwrap();
+        assert_eq!(
+            operation,
+            Operation::AddProportionalChange(
+                vec![
+                    ("Expenses:Food", dec!(50)),
+                    ("Debts:Anna", dec!(30)),
+                    ("Debts:Bob", dec!(20))
+                ],
+                Amount("€".to_owned(), dec!(90))
+            )
+        );
+        let mut tx = Transaction::new(NaiveDate::from_ymd(2020, 1, 10), "Dinner".to_owned());
+        operation.add_to_transation(&mut tx, DEBTS_PREFIX, |_| 2);
+        assert_eq!(
+            tx.changes["Debts:Bob"],
+            vec![Amount("€".to_owned(), dec!(18))]
+        );
+
+        let mut parser = Parser::new();
+        parser
+            .parse_words("pct € 90 Expenses:Food 60 Debts:Anna 40")
+            .unwrap();
+        assert!(parser.is_complete());
+        parser.parse_word("Debts:Bob").unwrap();
+        assert!(!parser.is_complete());
+
+        let mut parser = Parser::new();
+        parser
+            .parse_words("pct € 90 Expenses:Food 60 Debts:Anna 30")
+            .unwrap();
+        assert!(parser.operation().is_none());
+
+        let mut parser = Parser::new();
+        assert!(parser
+            .parse_words("pct € 90 Expenses:Food 60 Debts:Anna 50")
+            .is_err());
+        let mut parser = Parser::new();
+        assert!(parser.parse_words("tip € 90 Expenses:Food 60%").is_err());
+    }
+
+    #[test]
+    fn parse_finalize() {
+        let line = ["f", "Accounts:Checking"];
+        let mut parser = Parser::new();
+        assert_eq!(parser.next, TokenType::Operation);
+        assert!(parser.parse_word(line[0]).is_ok());
+        assert_eq!(parser.next, TokenType::Account);
+        assert!(parser.parse_word(line[1]).is_ok());
+        assert_eq!(parser.next, TokenType::Currency);
+        assert!(parser.parse_word("123").is_err());
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::Finalize("Accounts:Checking", None)
+        );
+    }
+
+    #[test]
+    fn parse_default_finalize() {
+        let mut parser = Parser::new().default_account(Some("Assets:Cash"));
+        assert!(parser.parse_word("f").is_ok());
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::Finalize("Assets:Cash", None)
+        );
+
+        let mut parser = Parser::new().default_account(Some("Assets:Cash"));
+        assert!(parser.parse_words("f Assets:Checking").is_ok());
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::Finalize("Assets:Checking", None)
+        );
+
+        let mut parser = Parser::new();
+        assert!(parser.parse_word("f").is_ok());
+        assert!(parser.operation().is_none());
+    }
+
+    #[test]
+    fn parse_currency_finalize() {
+        let line = ["f", "Accounts:Checking", "€"];
+        let mut parser = Parser::with_default_currency(Some("CZK"));
+        for word in &line {
+            assert!(parser.parse_word(word).is_ok());
+        }
+        assert_eq!(parser.next, TokenType::Eol);
+        assert!(parser.parse_word("blah").is_err());
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::Finalize("Accounts:Checking", Some("€"))
+        );
+
+        let mut parser = Parser::with_default_currency(Some("CZK"));
+        for word in &line[..2] {
+            assert!(parser.parse_word(word).is_ok());
+        }
+        assert!(parser.parse_word("12").is_err());
+    }
+
+    #[test]
+    fn parse_quoted_currency() {
+        let mut parser = Parser::new();
+        parser
+            .parse_words("a Assets:Broker \"FUND2030\" 10")
+            .unwrap();
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::AddSimpleChange(
+                "Assets:Broker",
+                Amount("\"FUND2030\"".to_owned(), dec!(10))
+            )
+        );
+
+        let mut parser = Parser::new();
+        parser.parse_words("f Assets:Broker \"Fund 2030\"").unwrap();
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::Finalize("Assets:Broker", Some("\"Fund 2030\""))
+        );
+
+        let mut parser = Parser::new();
+        let err = parser
+            .parse_words("a Assets:Broker FUND2030 10")
+            .unwrap_err();
+        assert_eq!(err.word, "FUND2030");
+    }
+
+    #[test]
+    fn account_aliases() {
+        let mut aliases = HashMap::new();
+        aliases.insert("g".to_owned(), "Expenses:Food:Groceries".to_owned());
+        aliases.insert("pete".to_owned(), "Debts:Pete".to_owned());
+        let mut parser = Parser::new().account_aliases(&aliases);
+        parser.parse_words("a g € 5").unwrap();
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::AddSimpleChange("Expenses:Food:Groceries", Amount("€".to_owned(), dec!(5)))
+        );
+
+        let mut parser = Parser::new().account_aliases(&aliases);
+        parser.parse_words("s g pete:0.4 € 10").unwrap();
+        assert_eq!(
+            parser.operation().unwrap().accounts(),
+            vec!["Expenses:Food:Groceries", "Debts:Pete"]
+        );
+
+        let mut parser = Parser::new().account_aliases(&aliases);
+        parser.parse_words("a Expenses:Rent € 500").unwrap();
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::AddSimpleChange("Expenses:Rent", Amount("€".to_owned(), dec!(500)))
+        );
+    }
+
+    #[test]
+    fn custom_keywords() {
+        let mut keywords = HashMap::new();
+        keywords.insert("+".to_owned(), "a".to_owned());
+        let mut parser = Parser::new().keywords(&keywords);
+        parser.parse_words("+ A:Food € 5").unwrap();
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::AddSimpleChange("A:Food", Amount("€".to_owned(), dec!(5)))
+        );
+
+        let mut parser = Parser::new().keywords(&keywords);
+        parser.parse_words("+- A:Food € 5").unwrap();
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::AddSimpleChange("A:Food", Amount("€".to_owned(), dec!(-5)))
+        );
+
+        let mut parser = Parser::new().keywords(&keywords);
+        parser.parse_words("a A:Food € 5").unwrap();
+        assert!(parser.operation().is_some());
+
+        let err = Parser::new().parse_words("A:Food € 5").unwrap_err();
+        assert_eq!(err.column, 1);
+        assert!(err.to_string().starts_with("Expecting a command"));
+        assert!(is_operation("split"));
+        assert!(!is_operation("+"));
+    }
+
+    #[test]
+    fn currency_aliases() {
+        let mut aliases = HashMap::new();
+        aliases.insert("EUR".to_owned(), "€".to_owned());
+        let mut parser = Parser::new().commodity_aliases(&aliases);
+        parser.parse_words("a Expenses EUR 5").unwrap();
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::AddSimpleChange("Expenses", Amount("€".to_owned(), dec!(5)))
+        );
+
+        let mut parser = Parser::new().commodity_aliases(&aliases);
+        parser.parse_words("f Assets EUR").unwrap();
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::Finalize("Assets", Some("€"))
+        );
+
+        let mut parser = Parser::new().commodity_aliases(&aliases);
+        parser.parse_words("a Expenses CZK 5").unwrap();
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::AddSimpleChange("Expenses", Amount("CZK".to_owned(), dec!(5)))
+        );
+    }
+
+    #[test]
+    fn parse_assert() {
+        let mut parser = Parser::new().amount_first(true);
+        for word in &["assert", "Assets:Cash", "€", "100"] {
+            assert!(parser.parse_word(word).is_ok());
+        }
+        let operation = parser.operation().unwrap();
+        assert_eq!(
+            operation,
+            Operation::AssertBalance("Assets:Cash", Amount("€".to_owned(), dec!(100)))
+        );
+        assert!(operation.amounts().is_empty());
+        assert_eq!(operation.currency(), Some("€"));
+    }
+
+    #[test]
+    fn parse_default_currency() {
+        let mut parser = Parser::with_default_currency(Some("€"));
+        for word in &["a", "Expenses", "12.34"] {
+            assert!(parser.parse_word(word).is_ok());
+        }
+        assert_eq!(parser.next, TokenType::Eol);
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::AddSimpleChange("Expenses", Amount("€".to_owned(), dec!(12.34)))
+        );
+
+        let mut parser = Parser::with_default_currency(Some("€"));
+        for word in &["a", "Expenses", "CZK", "12.34"] {
+            assert!(parser.parse_word(word).is_ok());
+        }
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::AddSimpleChange("Expenses", Amount("CZK".to_owned(), dec!(12.34)))
+        );
+
+        let mut parser = Parser::new();
+        assert!(parser.parse_word("a").is_ok());
+        assert!(parser.parse_word("Expenses").is_ok());
+        assert!(parser.parse_word("12.34").is_err());
+    }
+
+    #[test]
+    fn parse_amount_first() {
+        let line = ["a", "€", "12.34", "Expenses:Food"];
+        let mut parser = Parser::new().amount_first(true);
+        assert!(parser.parse_word(line[0]).is_ok());
+        assert_eq!(parser.next, TokenType::Currency);
+        assert!(parser.parse_word(line[1]).is_ok());
+        assert_eq!(parser.next, TokenType::Amount);
+        assert!(parser.parse_word(line[2]).is_ok());
+        assert_eq!(parser.next, TokenType::Account);
+        assert!(parser.parse_word(line[3]).is_ok());
+        assert_eq!(parser.next, TokenType::Eol);
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::AddSimpleChange("Expenses:Food", Amount("€".to_owned(), dec!(12.34)))
+        );
+
+        let line = ["s", "CZK", "120", "Expenses", "Debts:Peter"];
+        let mut parser = Parser::new().amount_first(true);
+        for word in &line {
+            assert!(parser.parse_word(word).is_ok());
+        }
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::AddSplitChange(
+                "Expenses",
+                "Debts:Peter",
+                vec![Amount("CZK".to_owned(), dec!(120))],
+                None
+            )
+        );
+
+        let mut parser = Parser::new().amount_first(true);
+        assert!(parser.parse_word("f").is_ok());
+        assert_eq!(parser.next, TokenType::Account);
+    }
+
+    #[test]
+    fn parse_cents() {
+        assert_eq!(parse_number("1299c").unwrap(), dec!(12.99));
+        assert_eq!(parse_number("50c").unwrap(), dec!(0.50));
+        assert_eq!(parse_number("-50c").unwrap(), dec!(-0.50));
+        assert_eq!(parse_number("12.99").unwrap(), dec!(12.99));
+        assert!(parse_number("12.5c").is_err());
+        assert!(parse_number("c").is_err());
+
+        let mut parser = Parser::new();
+        for word in &["a", "Expenses", "€", "1299c"] {
+            assert!(parser.parse_word(word).is_ok());
+        }
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::AddSimpleChange("Expenses", Amount("€".to_owned(), dec!(12.99)))
+        );
+    }
+
+    #[test]
+    fn padded_amounts() {
+        let mut precisions = HashMap::new();
+        precisions.insert("JPY".to_owned(), 0);
+        for (line, padded) in &[
+            ("a Expenses € 12.5", "12.50"),
+            ("a Expenses € 12.50", "12.50"),
+            ("a Expenses € 12", "12.00"),
+        ] {
+            let mut parser = Parser::new().pad_amounts(Some((&precisions, 2)));
+            parser.parse_words(line).unwrap();
+            assert_eq!(
+                parser.operation().unwrap().amounts()[0].1.to_string(),
+                *padded
+            );
+        }
+
+        let mut parser = Parser::new().pad_amounts(Some((&precisions, 2)));
+        parser
+            .parse_words("s Expenses Debts:Pete JPY 500 € 0.125")
+            .unwrap();
+        let amounts: Vec<String> = parser
+            .operation()
+            .unwrap()
+            .amounts()
+            .iter()
+            .map(|amount| amount.1.to_string())
+            .collect();
+        assert_eq!(amounts, vec!["500", "0.125"]);
+
+        let mut parser = Parser::new();
+        parser.parse_words("a Expenses € 12.5").unwrap();
+        assert_eq!(
+            parser.operation().unwrap().amounts()[0].1.to_string(),
+            "12.5"
+        );
+    }
+
+    #[test]
+    fn parse_multipliers() {
+        assert_eq!(parse_number("10k").unwrap().to_string(), "10000");
+        assert_eq!(parse_number("1.5k").unwrap().to_string(), "1500");
+        assert_eq!(parse_number("1.2345k").unwrap().to_string(), "1234.5");
+        assert_eq!(parse_number("-2m").unwrap(), dec!(-2000000));
+        assert_eq!(parse_number("12.50").unwrap().to_string(), "12.50");
+        assert!(parse_number("k").is_err());
+        assert!(parse_number("1.5kk").is_err());
+        assert!(parse_number("79228162514264337593543950335m").is_err());
+
+        let mut parser = Parser::new();
+        parser.parse_words("a Assets:Investment € 10k").unwrap();
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::AddSimpleChange("Assets:Investment", Amount("€".to_owned(), dec!(10000)))
+        );
+    }
+
+    #[test]
+    fn parse_fractions() {
+        assert_eq!(parse_number("1000/3").unwrap(), dec!(333.33));
+        assert_eq!(parse_number("100/4").unwrap(), dec!(25));
+        assert_eq!(parse_number("-10/3").unwrap(), dec!(-3.33));
+        assert_eq!(parse_number("1.000/3").unwrap(), dec!(0.333));
+        assert!(parse_number("100/0").is_err());
+        assert!(parse_number("100/").is_err());
+        assert!(parse_number("1/2/3").is_err());
+
+        let mut parser = Parser::with_default_currency(Some("€"));
+        for word in &["a", "Expenses", "1000/3"] {
+            assert!(parser.parse_word(word).is_ok());
+        }
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::AddSimpleChange("Expenses", Amount("€".to_owned(), dec!(333.33)))
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut parser = Parser::new();
+        assert!(parser.parse_word("x").is_err());
+        assert!(parser.parse_word("a").is_ok());
+        assert!(parser.parse_word("123").is_err());
+        assert!(parser.parse_word("1checking").is_err());
+        assert!(parser.parse_word("Expenses").is_ok());
+        assert!(parser.parse_word("123").is_err());
+        assert!(parser.parse_word("€").is_ok());
+        assert!(parser.parse_word("1a2b").is_err());
+        assert!(parser.parse_word("12.30").is_ok());
+        assert_eq!(
+            parser.operation().unwrap(),
+            Operation::AddSimpleChange("Expenses", Amount("€".to_owned(), dec!(12.30)))
+        );
+    }
+
+    #[test]
+    fn tx_header() {
+        let tx = parse_transaction_header("2020-02-27 Test transaction", "%Y-%m-%d", None).unwrap();
+        assert_eq!(tx.date, NaiveDate::from_ymd(2020, 2, 27));
+        assert_eq!(tx.description, "Test transaction");
+    }
+
+    #[test]
+    fn sticky_date() {
+        let sticky = Some(NaiveDate::from_ymd(2020, 2, 27));
+        let tx = parse_transaction_header("Lidl groceries", "%Y-%m-%d", sticky).unwrap();
+        assert_eq!(tx.date, NaiveDate::from_ymd(2020, 2, 27));
+        assert_eq!(tx.description, "Lidl groceries");
+        let tx = parse_transaction_header("2020-03-01 Lidl", "%Y-%m-%d", sticky).unwrap();
+        assert_eq!(tx.date, NaiveDate::from_ymd(2020, 3, 1));
+        assert_eq!(tx.description, "Lidl");
+        let tx = parse_transaction_header("3 coffees", "%Y-%m-%d", sticky).unwrap();
+        assert_eq!(tx.date, NaiveDate::from_ymd(2020, 2, 27));
+        assert_eq!(tx.description, "3 coffees");
+        assert!(parse_transaction_header("Lidl groceries", "%Y-%m-%d", None).is_err());
+    }
+
+    #[test]
+    fn date_shortcuts() {
+        let today = NaiveDate::from_ymd(2020, 3, 1);
+        assert_eq!(parse_date(".", "%Y-%m-%d", today).unwrap(), today);
+        assert_eq!(parse_date("today", "%Y-%m-%d", today).unwrap(), today);
+        assert_eq!(
+            parse_date("yesterday", "%Y-%m-%d", today).unwrap(),
+            NaiveDate::from_ymd(2020, 2, 29)
+        );
+        assert_eq!(
+            parse_date("2020-02-27", "%Y-%m-%d", today).unwrap(),
+            NaiveDate::from_ymd(2020, 2, 27)
+        );
+        let tx = parse_transaction_header("today Groceries", "%Y-%m-%d", None).unwrap();
+        assert_eq!(tx.description, "Groceries");
+    }
+
+    #[test]
+    fn relative_dates() {
+        let today = NaiveDate::from_ymd(2020, 3, 1);
+        assert_eq!(
+            parse_date("-1", "%Y-%m-%d", today).unwrap(),
+            NaiveDate::from_ymd(2020, 2, 29)
+        );
+        assert_eq!(parse_date("0", "%Y-%m-%d", today).unwrap(), today);
+        assert_eq!(
+            parse_date("+2", "%Y-%m-%d", today).unwrap(),
+            NaiveDate::from_ymd(2020, 3, 3)
+        );
+        assert!(parse_date("-2147483648", "%Y-%m-%d", today).is_err());
+        assert!(parse_date("1x", "%Y-%m-%d", today).is_err());
+        let tx = parse_transaction_header("-3 Groceries", "%Y-%m-%d", None).unwrap();
+        assert_eq!(tx.description, "Groceries");
+    }
+
+    #[test]
+    fn header_date() {
+        assert_eq!(
+            parse_header_date(&["2024-01-02"], "%Y-%m-%d").unwrap(),
+            NaiveDate::from_ymd(2024, 1, 2)
+        );
+        assert!(parse_header_date(&["2024-13-02"], "%Y-%m-%d").is_err());
+        assert!(parse_header_date(&[], "%Y-%m-%d").is_err());
+        assert!(parse_header_date(&["2024-01-02", "2024-01-03"], "%Y-%m-%d").is_err());
+    }
+
+    #[test]
+    fn tokenization() {
+        assert_eq!(
+            tokenize("a Expenses € 5"),
+            vec![(0, "a"), (2, "Expenses"), (11, "€"), (15, "5")]
+        );
+        assert_eq!(
+            tokenize("  a   Expenses\t5  "),
+            vec![(2, "a"), (6, "Expenses"), (15, "5")]
+        );
+        assert_eq!(
+            tokenize("a Assets \"Fund 2030\" 10"),
+            vec![(0, "a"), (2, "Assets"), (9, "\"Fund 2030\""), (21, "10")]
+        );
+        assert_eq!(tokenize("   "), vec![]);
+        assert_eq!(words(" s  A B "), vec!["s", "A", "B"]);
+    }
+
+    #[test]
+    fn word_errors() {
+        let mut parser = Parser::new();
+        let err = parser
+            .parse_words("a Expenses € notanumber extra")
+            .unwrap_err();
+        assert_eq!(err.word, "notanumber");
+        assert_eq!(err.column, 14);
+        assert!(err.to_string().ends_with(" 'notanumber' at column 14"));
+        assert_eq!(parser.next, TokenType::Amount);
+        assert!(parser.operation().is_none());
+
+        let mut parser = Parser::new();
+        assert!(parser.parse_words("a Expenses € 5").is_ok());
+        assert!(parser.operation().is_some());
+    }
+
+    #[test]
+    fn recent_accounts() {
+        let recent = vec!["Expenses:Food".to_owned(), "Assets:Cash".to_owned()];
+        assert_eq!(
+            expand_recent("s @1  Debts:Pete € 5", &recent).unwrap(),
+            "s Expenses:Food  Debts:Pete € 5"
+        );
+        assert_eq!(
+            expand_recent("t @2 @1 € 5", &recent).unwrap(),
+            "t Assets:Cash Expenses:Food € 5"
+        );
+        assert_eq!(expand_recent("a @x € 5", &recent).unwrap(), "a @x € 5");
+        assert!(expand_recent("a @3 € 5", &recent).is_err());
+        assert!(expand_recent("a @0 € 5", &recent).is_err());
+
+        let mut parser = Parser::new();
+        parser.parse_words("t A B € 5").unwrap();
+        assert_eq!(parser.operation().unwrap().accounts(), vec!["A", "B"]);
+    }
+
+    #[test]
+    fn words_at_cursor() {
+        let line = "  a  Expe  ";
+        assert_eq!(word_at(line, 0), (0, ""));
+        assert_eq!(word_at(line, 3), (2, "a"));
+        assert_eq!(word_at(line, 4), (4, ""));
+        assert_eq!(word_at(line, 7), (5, "Ex"));
+        assert_eq!(word_at(line, 9), (5, "Expe"));
+        assert_eq!(word_at(line, 11), (11, ""));
+    }
+
+    #[test]
+    fn tags() {
+        assert_eq!(
+            parse_tag(&["trip", "Japan", "2024"]).unwrap(),
+            ("trip".to_owned(), "Japan 2024".to_owned())
+        );
+        assert!(parse_tag(&["trip"]).is_err());
+        assert!(parse_tag(&["trip:", "Japan"]).is_err());
+        assert!(parse_tag(&[]).is_err());
+    }
+
+    #[test]
+    fn tx_header_custom_date_format() {
+        let tx = parse_transaction_header("27.02.2020 Test transaction", "%d.%m.%Y", None).unwrap();
         assert_eq!(tx.date, NaiveDate::from_ymd(2020, 2, 27));
         assert_eq!(tx.description, "Test transaction");
+        assert!(parse_transaction_header("2020-02-27 Test transaction", "%d.%m.%Y", None).is_err());
     }
 }
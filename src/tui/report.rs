@@ -0,0 +1,86 @@
+use std::fmt;
+
+/// The kind of an error, as written in machine-readable errors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    /// The input line couldn't be parsed.
+    Parse,
+    /// Ledger couldn't read the journal.
+    Ledger,
+    /// The journal couldn't be changed.
+    Save,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            ErrorKind::Parse => "parse",
+            ErrorKind::Ledger => "ledger",
+            ErrorKind::Save => "save",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+/// Prints errors either as friendly messages or, for scripts piping input
+/// into splitter, as lines like `ERROR line=12 kind=parse msg=...`.
+pub struct ErrorReporter {
+    machine_readable: bool,
+    line: usize,
+}
+
+impl ErrorReporter {
+    pub fn new(machine_readable: bool) -> ErrorReporter {
+        ErrorReporter {
+            machine_readable,
+            line: 0,
+        }
+    }
+
+    /// Moves to the next input line, which the following errors refer to.
+    pub fn next_line(&mut self) {
+        self.line += 1;
+    }
+
+    pub fn report(&self, kind: ErrorKind, message: impl fmt::Display) {
+        println!("{}", self.format(kind, message));
+    }
+
+    /// Machine-readable errors are kept on a single line, with the message
+    /// last so it can contain spaces.
+    fn format(&self, kind: ErrorKind, message: impl fmt::Display) -> String {
+        if self.machine_readable {
+            let message = message.to_string().replace('\n', " ");
+            format!("ERROR line={} kind={} msg={}", self.line, kind, message)
+        } else {
+            message.to_string()
+        }
+    }
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn error_formats() {
+        let mut reporter = ErrorReporter::new(false);
+        reporter.next_line();
+        assert_eq!(
+            reporter.format(ErrorKind::Parse, "Invalid amount"),
+            "Invalid amount"
+        );
+
+        let mut reporter = ErrorReporter::new(true);
+        reporter.next_line();
+        reporter.next_line();
+        assert_eq!(
+            reporter.format(ErrorKind::Parse, "Invalid amount '1x' at column 14"),
+            "ERROR line=2 kind=parse msg=Invalid amount '1x' at column 14"
+        );
+        assert_eq!(
+            reporter.format(ErrorKind::Save, "Journal broken:\nline 3"),
+            "ERROR line=2 kind=save msg=Journal broken: line 3"
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use crate::transaction::Transaction;
+
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// Unfinished transactions older than this aren't offered for resuming.
+const MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Returns the file storing the unfinished transaction of the ledger file,
+/// in the user's XDG state directory, so that neither other users nor
+/// sessions with other journals can overwrite it.
+fn state_path(path_to_ledger: &str) -> Option<PathBuf> {
+    let state_dir = env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state"))
+        })?;
+    let mut hasher = DefaultHasher::new();
+    path_to_ledger.hash(&mut hasher);
+    Some(
+        state_dir
+            .join("splitter")
+            .join(format!("unfinished-{:016x}.txt", hasher.finish())),
+    )
+}
+
+/// Stores the unfinished transaction, together with the path of the ledger
+/// file it belongs to.
+pub fn save(path_to_ledger: &str, tx: &Transaction) -> io::Result<()> {
+    let path = state_path(path_to_ledger)
+        .ok_or_else(|| io::Error::other("neither XDG_STATE_HOME nor HOME is set"))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // The old file is removed rather than truncated, so that a link put in
+    // its place isn't followed.
+    match fs::remove_file(&path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    writeln!(file, "{}", path_to_ledger)?;
+    write!(file, "{}", tx)?;
+    file.sync_all()
+}
+
+/// Takes the stored unfinished transaction, if there is a recent one for the
+/// ledger file.
+pub fn take(path_to_ledger: &str) -> Option<Transaction> {
+    let path = state_path(path_to_ledger)?;
+    let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+    let mut contents = String::new();
+    File::open(&path)
+        .and_then(|mut f| f.read_to_string(&mut contents))
+        .ok()?;
+    let (saved_path, tx) = contents.split_at(contents.find('\n')?);
+    if saved_path != path_to_ledger {
+        return None;
+    }
+    let _ = fs::remove_file(&path);
+    let age = SystemTime::now()
+        .duration_since(modified)
+        .unwrap_or_default();
+    if age > MAX_AGE {
+        return None;
+    }
+    tx[1..].parse().ok()
+}